@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::NodeHello;
+
+/// Decides which nodes are allowed to submit solutions to the master.
+///
+/// Every list is optional: an empty list lets everyone through for that
+/// check.
+#[derive(Debug, Default)]
+pub struct Admission {
+    tokens: HashSet<String>,
+    miners: HashSet<Pubkey>,
+    ips: HashSet<IpAddr>,
+    rejected: AtomicU64,
+}
+
+impl Admission {
+    pub fn new(tokens: Vec<String>, miners: Vec<Pubkey>, ips: Vec<IpAddr>) -> Self {
+        Self {
+            tokens: tokens.into_iter().collect(),
+            miners: miners.into_iter().collect(),
+            ips: ips.into_iter().collect(),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Checked as soon as the TCP connection is accepted, before the
+    /// websocket handshake.
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), String> {
+        if !self.ips.is_empty() && !self.ips.contains(&ip) {
+            return self.reject(format!("ip {} is not allowed", ip));
+        }
+        Ok(())
+    }
+
+    /// Checked on the node's hello, before any solution is accepted.
+    pub fn check_hello(&self, ip: IpAddr, hello: &NodeHello) -> Result<(), String> {
+        if !self.tokens.is_empty() {
+            match &hello.token {
+                Some(token) if self.tokens.contains(token) => {}
+                Some(_) => return self.reject(format!("invalid token from {}", ip)),
+                None => return self.reject(format!("missing token from {}", ip)),
+            }
+        }
+        if !self.miners.is_empty() && !self.miners.contains(&hello.miner_authority) {
+            return self.reject(format!(
+                "miner authority {} from {} is not allowed",
+                hello.miner_authority, ip
+            ));
+        }
+        Ok(())
+    }
+
+    /// Number of connections turned away so far.
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    fn reject(&self, reason: String) -> Result<(), String> {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(%reason, "rejected connection");
+        Err(reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(miner_authority: Pubkey, token: Option<&str>) -> NodeHello {
+        NodeHello {
+            miner_authority,
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn empty_lists_let_everyone_through() {
+        let admission = Admission::default();
+        let ip = "10.0.0.1".parse().unwrap();
+        assert!(admission.check_ip(ip).is_ok());
+        assert!(admission
+            .check_hello(ip, &hello(Pubkey::new_unique(), None))
+            .is_ok());
+        assert_eq!(admission.rejected_count(), 0);
+    }
+
+    #[test]
+    fn nodes_need_a_known_token() {
+        let admission = Admission::new(vec!["secret".to_string()], vec![], vec![]);
+        let ip = "10.0.0.1".parse().unwrap();
+        let miner = Pubkey::new_unique();
+        assert!(admission
+            .check_hello(ip, &hello(miner, Some("secret")))
+            .is_ok());
+        assert_eq!(
+            admission.check_hello(ip, &hello(miner, Some("guess"))),
+            Err("invalid token from 10.0.0.1".to_string())
+        );
+        assert_eq!(
+            admission.check_hello(ip, &hello(miner, None)),
+            Err("missing token from 10.0.0.1".to_string())
+        );
+        assert_eq!(admission.rejected_count(), 2);
+    }
+
+    #[test]
+    fn only_allowed_miners_get_through() {
+        let allowed = Pubkey::new_unique();
+        let admission = Admission::new(vec![], vec![allowed], vec![]);
+        let ip = "10.0.0.1".parse().unwrap();
+        let other = Pubkey::new_unique();
+        assert!(admission.check_hello(ip, &hello(allowed, None)).is_ok());
+        assert_eq!(
+            admission.check_hello(ip, &hello(other, None)),
+            Err(format!(
+                "miner authority {} from 10.0.0.1 is not allowed",
+                other
+            ))
+        );
+        assert_eq!(admission.rejected_count(), 1);
+    }
+
+    #[test]
+    fn only_allowed_ips_get_through() {
+        let admission = Admission::new(vec![], vec![], vec!["10.0.0.1".parse().unwrap()]);
+        assert!(admission.check_ip("10.0.0.1".parse().unwrap()).is_ok());
+        assert_eq!(
+            admission.check_ip("10.0.0.2".parse().unwrap()),
+            Err("ip 10.0.0.2 is not allowed".to_string())
+        );
+        assert_eq!(admission.rejected_count(), 1);
+    }
+
+    #[test]
+    fn the_token_is_checked_before_the_miner() {
+        let allowed = Pubkey::new_unique();
+        let admission = Admission::new(vec!["secret".to_string()], vec![allowed], vec![]);
+        let ip = "10.0.0.1".parse().unwrap();
+        assert!(admission
+            .check_hello(ip, &hello(Pubkey::new_unique(), Some("guess")))
+            .unwrap_err()
+            .starts_with("invalid token"));
+        assert!(admission
+            .check_hello(ip, &hello(allowed, Some("secret")))
+            .is_ok());
+        assert_eq!(admission.rejected_count(), 1);
+    }
+}
@@ -0,0 +1,469 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use subtle::ConstantTimeEq;
+use tokio::sync::{mpsc, watch};
+
+use crate::history::{to_hex, unix_seconds, write_csv, write_jsonl, EpochRecord, HistoryQuery};
+use crate::registry::{WorkerInfo, WorkerRegistry};
+use crate::status::{AuthorityStatus, FeePayerStatus, PoolStatus};
+use crate::SubmittedSolutionEnum;
+
+/// Everything the HTTP API reads from or acts upon.
+#[derive(Clone)]
+pub struct ApiState {
+    pub status: PoolStatus,
+    pub workers: WorkerRegistry,
+    pub solution_tx: mpsc::Sender<SubmittedSolutionEnum>,
+    /// Bearer token required by the `/admin` routes. They are disabled when
+    /// it is not set.
+    pub admin_token: Option<String>,
+}
+
+type ApiError = (StatusCode, String);
+
+/// Serves the status and admin API until `shutdown` flips to `true`.
+pub async fn start_api_server(host: String, state: ApiState, mut shutdown: watch::Receiver<bool>) {
+    let addr: SocketAddr = host.parse().expect("invalid api address");
+    axum::Server::bind(&addr)
+        .serve(router(state).into_make_service())
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.changed().await;
+        })
+        .await
+        .unwrap();
+}
+
+fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/status", get(get_status))
+        .route("/authorities", get(get_authorities))
+        .route("/miners", get(get_miners))
+        .route("/workers", get(get_workers))
+        .route("/epochs", get(get_epochs))
+        .route("/epochs/export", get(export_epochs))
+        .route("/admin/workers/:id/kick", post(kick_worker))
+        .route("/admin/pause", post(pause))
+        .route("/admin/resume", post(resume))
+        .route("/admin/epochs/:authority", post(force_epoch))
+        .with_state(state)
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    paused: bool,
+    /// Solutions are turned away until the fee payer is topped up.
+    out_of_funds: bool,
+    fee_payer: Option<FeePayerResponse>,
+    connected_workers: usize,
+    authorities: Vec<AuthorityResponse>,
+}
+
+#[derive(Serialize)]
+struct FeePayerResponse {
+    address: String,
+    lamports: u64,
+    low: bool,
+}
+
+impl From<FeePayerStatus> for FeePayerResponse {
+    fn from(status: FeePayerStatus) -> Self {
+        Self {
+            address: status.address.to_string(),
+            lamports: status.lamports,
+            low: status.low,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuthorityResponse {
+    staking_authority: String,
+    challenge: String,
+    best_difficulty: u32,
+    best_submitter: Option<String>,
+    solutions: usize,
+}
+
+impl AuthorityResponse {
+    fn new(staking_authority: &Pubkey, status: &AuthorityStatus) -> Self {
+        Self {
+            staking_authority: staking_authority.to_string(),
+            challenge: to_hex(&status.challenge),
+            best_difficulty: status.best_difficulty,
+            best_submitter: status.best_submitter.map(|miner| miner.to_string()),
+            solutions: status.solutions,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MinerResponse {
+    miner_authority: String,
+    shares: u64,
+    balance: u64,
+}
+
+#[derive(Serialize)]
+struct WorkerResponse {
+    id: u64,
+    address: String,
+    miner_authority: String,
+    connected_since: u64,
+    last_seen: u64,
+}
+
+impl From<WorkerInfo> for WorkerResponse {
+    fn from(worker: WorkerInfo) -> Self {
+        Self {
+            id: worker.id,
+            address: worker.address.to_string(),
+            miner_authority: worker.miner_authority.to_string(),
+            connected_since: unix_seconds(worker.connected_since),
+            last_seen: unix_seconds(worker.last_seen),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct EpochsQuery {
+    authority: Option<String>,
+    since: Option<u64>,
+    until: Option<u64>,
+    limit: Option<usize>,
+    /// Only read by the export route.
+    format: Option<String>,
+}
+
+impl EpochsQuery {
+    fn parse(self) -> Result<HistoryQuery, ApiError> {
+        let staking_authority = self
+            .authority
+            .map(|authority| parse_pubkey(&authority))
+            .transpose()?;
+        Ok(HistoryQuery {
+            staking_authority,
+            since: self.since,
+            until: self.until,
+            limit: self.limit,
+        })
+    }
+}
+
+async fn get_status(State(state): State<ApiState>) -> Json<StatusResponse> {
+    let status = state.status.read();
+    Json(StatusResponse {
+        paused: state.status.is_paused(),
+        out_of_funds: state.status.is_out_of_funds(),
+        fee_payer: status.fee_payer.map(FeePayerResponse::from),
+        connected_workers: state.workers.len(),
+        authorities: status
+            .authorities
+            .iter()
+            .map(|(sa, authority)| AuthorityResponse::new(sa, authority))
+            .collect(),
+    })
+}
+
+async fn get_authorities(State(state): State<ApiState>) -> Json<Vec<AuthorityResponse>> {
+    let status = state.status.read();
+    Json(
+        status
+            .authorities
+            .iter()
+            .map(|(sa, authority)| AuthorityResponse::new(sa, authority))
+            .collect(),
+    )
+}
+
+async fn get_miners(State(state): State<ApiState>) -> Json<Vec<MinerResponse>> {
+    let status = state.status.read();
+    let mut miners: Vec<_> = status
+        .ledger
+        .accounts()
+        .map(|(miner, account)| MinerResponse {
+            miner_authority: miner.to_string(),
+            shares: account.shares,
+            balance: account.balance,
+        })
+        .collect();
+    miners.sort_by_key(|miner| std::cmp::Reverse(miner.balance));
+    Json(miners)
+}
+
+async fn get_workers(State(state): State<ApiState>) -> Json<Vec<WorkerResponse>> {
+    Json(
+        state
+            .workers
+            .workers()
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+    )
+}
+
+/// Most recent epochs first.
+async fn get_epochs(
+    State(state): State<ApiState>,
+    Query(query): Query<EpochsQuery>,
+) -> Result<Json<Vec<EpochRecord>>, ApiError> {
+    let query = query.parse()?;
+    let status = state.status.read();
+    Ok(Json(
+        status.history.query(&query).into_iter().cloned().collect(),
+    ))
+}
+
+/// Matching epochs, oldest first, as `format=csv` (the default) or
+/// `format=jsonl`.
+async fn export_epochs(
+    State(state): State<ApiState>,
+    Query(mut query): Query<EpochsQuery>,
+) -> Result<([(header::HeaderName, &'static str); 1], Vec<u8>), ApiError> {
+    let format = query.format.take().unwrap_or_else(|| "csv".to_string());
+    let query = query.parse()?;
+    let status = state.status.read();
+    let mut records = status.history.query(&query);
+    records.reverse();
+    let mut body = vec![];
+    let content_type = match format.as_str() {
+        "csv" => {
+            write_csv(&mut body, records).unwrap();
+            "text/csv"
+        }
+        "jsonl" => {
+            write_jsonl(&mut body, records).unwrap();
+            "application/x-ndjson"
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("unknown export format {}", format),
+            ))
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, content_type)], body))
+}
+
+async fn kick_worker(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&state, &headers)?;
+    if state.workers.kick(id) {
+        tracing::info!(worker_id = id, "admin kicked worker");
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("no worker {}", id)))
+    }
+}
+
+async fn pause(State(state): State<ApiState>, headers: HeaderMap) -> Result<StatusCode, ApiError> {
+    authorize(&state, &headers)?;
+    tracing::info!("admin paused submissions");
+    state.status.set_paused(true);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn resume(State(state): State<ApiState>, headers: HeaderMap) -> Result<StatusCode, ApiError> {
+    authorize(&state, &headers)?;
+    tracing::info!("admin resumed submissions");
+    state.status.set_paused(false);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn force_epoch(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Path(authority): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    authorize(&state, &headers)?;
+    let staking_authority = parse_pubkey(&authority)?;
+    if !state
+        .status
+        .read()
+        .authorities
+        .contains_key(&staking_authority)
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("unknown staking authority {}", staking_authority),
+        ));
+    }
+    tracing::info!(authority = %staking_authority, "admin forced a new epoch");
+    state
+        .solution_tx
+        .send(SubmittedSolutionEnum::NewEpoch(staking_authority))
+        .await
+        .map_err(|_| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "master is not running".to_string(),
+            )
+        })?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+fn authorize(state: &ApiState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(token) = &state.admin_token else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "admin routes are disabled".to_string(),
+        ));
+    };
+    let expected = format!("Bearer {}", token);
+    // Compared in constant time, so that timing does not leak the token.
+    match headers.get("authorization") {
+        Some(value) if bool::from(value.as_bytes().ct_eq(expected.as_bytes())) => Ok(()),
+        _ => Err((StatusCode::UNAUTHORIZED, "invalid admin token".to_string())),
+    }
+}
+
+fn parse_pubkey(value: &str) -> Result<Pubkey, ApiError> {
+    Pubkey::from_str(value)
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("invalid pubkey {}", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use tokio::time::timeout;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    const TOKEN: &str = "s3cret";
+
+    fn state(admin_token: Option<&str>) -> (ApiState, mpsc::Receiver<SubmittedSolutionEnum>) {
+        let (solution_tx, solution_rx) = mpsc::channel(8);
+        let state = ApiState {
+            status: PoolStatus::new(),
+            workers: WorkerRegistry::new(),
+            solution_tx,
+            admin_token: admin_token.map(str::to_string),
+        };
+        (state, solution_rx)
+    }
+
+    async fn post(state: &ApiState, uri: &str, authorization: Option<&str>) -> StatusCode {
+        let mut request = Request::post(uri);
+        if let Some(authorization) = authorization {
+            request = request.header("authorization", authorization);
+        }
+        router(state.clone())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    async fn admin(state: &ApiState, uri: &str) -> StatusCode {
+        post(state, uri, Some(&format!("Bearer {}", TOKEN))).await
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_disabled_without_a_token() {
+        let (state, _solutions) = state(None);
+        for uri in [
+            "/admin/pause".to_string(),
+            "/admin/resume".to_string(),
+            "/admin/workers/0/kick".to_string(),
+            format!("/admin/epochs/{}", Pubkey::new_unique()),
+        ] {
+            assert_eq!(admin(&state, &uri).await, StatusCode::FORBIDDEN, "{}", uri);
+        }
+        assert!(!state.status.is_paused());
+    }
+
+    #[tokio::test]
+    async fn admin_routes_need_the_bearer_token() {
+        let (state, _solutions) = state(Some(TOKEN));
+        for authorization in [
+            None,
+            Some("Bearer guess"),
+            Some(TOKEN),
+            Some("Bearer s3cret2"),
+        ] {
+            assert_eq!(
+                post(&state, "/admin/pause", authorization).await,
+                StatusCode::UNAUTHORIZED,
+                "{:?}",
+                authorization
+            );
+        }
+        assert!(!state.status.is_paused());
+
+        assert_eq!(admin(&state, "/admin/pause").await, StatusCode::NO_CONTENT);
+        assert!(state.status.is_paused());
+        assert_eq!(admin(&state, "/admin/resume").await, StatusCode::NO_CONTENT);
+        assert!(!state.status.is_paused());
+    }
+
+    #[tokio::test]
+    async fn workers_can_be_kicked() {
+        let (state, _solutions) = state(Some(TOKEN));
+        let address = "127.0.0.1:9001".parse().unwrap();
+        let (id, kick) = state.workers.register(address, Pubkey::new_unique());
+
+        let uri = format!("/admin/workers/{}/kick", id);
+        assert_eq!(admin(&state, &uri).await, StatusCode::NO_CONTENT);
+        timeout(Duration::from_secs(1), kick.notified())
+            .await
+            .expect("the worker was not kicked");
+        let uri = format!("/admin/workers/{}/kick", id + 1);
+        assert_eq!(admin(&state, &uri).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn epochs_can_be_forced() {
+        let (state, mut solutions) = state(Some(TOKEN));
+        let authority = Pubkey::new_unique();
+        state
+            .status
+            .write()
+            .authorities
+            .insert(authority, AuthorityStatus::default());
+
+        let uri = format!("/admin/epochs/{}", authority);
+        assert_eq!(admin(&state, &uri).await, StatusCode::ACCEPTED);
+        assert!(matches!(
+            solutions.try_recv(),
+            Ok(SubmittedSolutionEnum::NewEpoch(forced)) if forced == authority
+        ));
+
+        let unknown = format!("/admin/epochs/{}", Pubkey::new_unique());
+        assert_eq!(admin(&state, &unknown).await, StatusCode::NOT_FOUND);
+        assert_eq!(
+            admin(&state, "/admin/epochs/not-a-pubkey").await,
+            StatusCode::BAD_REQUEST
+        );
+
+        drop(solutions);
+        assert_eq!(admin(&state, &uri).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn the_status_is_public() {
+        let (state, _solutions) = state(Some(TOKEN));
+        state.status.set_paused(true);
+        let response = router(state)
+            .oneshot(Request::get("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["paused"], true);
+        assert_eq!(status["connected_workers"], 0);
+    }
+}
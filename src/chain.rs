@@ -0,0 +1,184 @@
+use ore_api::consts::{BUS_ADDRESSES, CONFIG_ADDRESS, MINT_ADDRESS};
+use ore_api::state::{Bus, Config, Proof};
+use ore_utils::AccountDeserialize;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+    rpc_client::RpcClient,
+    rpc_config::{RpcSendTransactionConfig, RpcSimulateTransactionConfig},
+};
+use solana_sdk::{
+    clock::Clock,
+    commitment_config::CommitmentLevel,
+    hash::Hash,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::Signature,
+    sysvar,
+    transaction::{self, Transaction},
+};
+use solana_transaction_status::{TransactionConfirmationStatus, UiTransactionEncoding};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::proof_pubkey;
+
+const RPC_RETRIES: usize = 10;
+
+/// Chain operations the pool relies on. Implemented on top of [`RpcClient`],
+/// and by `fake_chain::FakeChain` (behind the `test-utils` feature) for tests.
+#[allow(clippy::result_large_err)]
+pub trait Chain: Send + Sync {
+    /// The proof of `authority`, or `None` when it has not been opened yet.
+    fn get_proof(&self, authority: Pubkey) -> ClientResult<Option<Proof>>;
+
+    fn get_clock(&self) -> ClientResult<Clock>;
+
+    /// The program-wide ORE config.
+    fn get_config(&self) -> ClientResult<Config>;
+
+    /// Every bus, in the order of `BUS_ADDRESSES`.
+    fn get_buses(&self) -> ClientResult<Vec<Bus>>;
+
+    /// ORE held in the associated token account of `owner`, 0 when it does
+    /// not exist.
+    fn get_token_balance(&self, owner: Pubkey) -> ClientResult<u64>;
+
+    /// Lamports held by `address`, 0 when the account does not exist.
+    fn get_balance(&self, address: Pubkey) -> ClientResult<u64>;
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash>;
+
+    fn send_transaction(&self, tx: &Transaction) -> ClientResult<Signature>;
+
+    /// Runs `tx` against the current state without committing it.
+    fn simulate_transaction(&self, tx: &Transaction) -> ClientResult<Simulation>;
+
+    /// Outcome of a sent transaction, or `None` while it is not confirmed.
+    fn confirm_transaction(
+        &self,
+        signature: &Signature,
+    ) -> ClientResult<Option<transaction::Result<()>>>;
+}
+
+/// What a transaction would do if it was sent now.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    pub result: transaction::Result<()>,
+    /// Compute units used, when the chain reports them.
+    pub units_consumed: Option<u64>,
+    pub logs: Vec<String>,
+}
+
+#[allow(clippy::result_large_err)]
+impl Chain for RpcClient {
+    fn get_proof(&self, authority: Pubkey) -> ClientResult<Option<Proof>> {
+        let account = self
+            .get_account_with_commitment(&proof_pubkey(authority), self.commitment())?
+            .value;
+        account
+            .map(|account| parse_account::<Proof>(&account.data))
+            .transpose()
+    }
+
+    fn get_clock(&self) -> ClientResult<Clock> {
+        let data = self.get_account_data(&sysvar::clock::ID)?;
+        bincode::deserialize::<Clock>(&data).map_err(|err| custom_error(err.to_string()))
+    }
+
+    fn get_config(&self) -> ClientResult<Config> {
+        parse_account::<Config>(&self.get_account_data(&CONFIG_ADDRESS)?)
+    }
+
+    fn get_buses(&self) -> ClientResult<Vec<Bus>> {
+        self.get_multiple_accounts(&BUS_ADDRESSES)?
+            .into_iter()
+            .map(|account| {
+                let account = account.ok_or_else(|| custom_error("missing bus account"))?;
+                parse_account::<Bus>(&account.data)
+            })
+            .collect()
+    }
+
+    fn get_token_balance(&self, owner: Pubkey) -> ClientResult<u64> {
+        let address = get_associated_token_address(&owner, &MINT_ADDRESS);
+        let Some(account) = self
+            .get_account_with_commitment(&address, self.commitment())?
+            .value
+        else {
+            return Ok(0);
+        };
+        spl_token::state::Account::unpack(&account.data)
+            .map(|account| account.amount)
+            .map_err(|err| custom_error(err.to_string()))
+    }
+
+    fn get_balance(&self, address: Pubkey) -> ClientResult<u64> {
+        Ok(self
+            .get_balance_with_commitment(&address, self.commitment())?
+            .value)
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        let (hash, _slot) = self.get_latest_blockhash_with_commitment(self.commitment())?;
+        Ok(hash)
+    }
+
+    fn send_transaction(&self, tx: &Transaction) -> ClientResult<Signature> {
+        let send_cfg = RpcSendTransactionConfig {
+            skip_preflight: true,
+            preflight_commitment: Some(CommitmentLevel::Confirmed),
+            encoding: Some(UiTransactionEncoding::Base64),
+            max_retries: Some(RPC_RETRIES),
+            min_context_slot: None,
+        };
+        self.send_transaction_with_config(tx, send_cfg)
+    }
+
+    fn simulate_transaction(&self, tx: &Transaction) -> ClientResult<Simulation> {
+        let simulate_cfg = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            commitment: Some(self.commitment()),
+            encoding: Some(UiTransactionEncoding::Base64),
+            ..Default::default()
+        };
+        let result = self
+            .simulate_transaction_with_config(tx, simulate_cfg)?
+            .value;
+        Ok(Simulation {
+            result: result.err.map_or(Ok(()), Err),
+            units_consumed: result.units_consumed,
+            logs: result.logs.unwrap_or_default(),
+        })
+    }
+
+    fn confirm_transaction(
+        &self,
+        signature: &Signature,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        let statuses = self.get_signature_statuses(&[*signature])?.value;
+        let Some(status) = statuses.into_iter().flatten().next() else {
+            return Ok(None);
+        };
+        if let Some(err) = status.err {
+            return Ok(Some(Err(err)));
+        }
+        Ok(match status.confirmation_status {
+            Some(TransactionConfirmationStatus::Confirmed)
+            | Some(TransactionConfirmationStatus::Finalized) => Some(Ok(())),
+            Some(TransactionConfirmationStatus::Processed) | None => None,
+        })
+    }
+}
+
+pub(crate) fn custom_error(message: impl Into<String>) -> ClientError {
+    ClientError {
+        request: None,
+        kind: ClientErrorKind::Custom(message.into()),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_account<T: AccountDeserialize + Copy>(data: &[u8]) -> ClientResult<T> {
+    T::try_from_bytes(data)
+        .copied()
+        .map_err(|err| custom_error(err.to_string()))
+}
@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::chain::Chain;
+
+/// Source of time for epoch scheduling, hashing deadlines and submissions.
+pub trait Clock: Send + Sync {
+    /// Time elapsed since the unix epoch.
+    fn now(&self) -> Duration;
+
+    /// Blocks the calling thread until `duration` has passed on this clock.
+    fn sleep(&self, duration: Duration);
+
+    /// Blocks the calling thread until this clock reads `deadline`.
+    fn sleep_until(&self, deadline: Duration) {
+        let now = self.now();
+        if deadline > now {
+            self.sleep(deadline - now);
+        }
+    }
+
+    fn unix_timestamp(&self) -> i64 {
+        self.now().as_secs() as i64
+    }
+
+    /// Re-aligns with the chain, for clocks that follow it. Called whenever
+    /// fresh on-chain state is read.
+    fn sync(&self) {}
+}
+
+/// Wall time of the local machine.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// Wall time shifted to agree with the chain's `Clock` sysvar, which is what
+/// the ORE program checks `last_hash_at` against. Local clocks and the
+/// validators' timestamp oracle commonly disagree by a few seconds.
+pub struct ChainClock {
+    chain: Arc<dyn Chain>,
+    // chain time minus wall time, in milliseconds
+    offset: AtomicI64,
+}
+
+impl ChainClock {
+    /// Starts uncorrected until the first [`Clock::sync`].
+    pub fn new(chain: Arc<dyn Chain>) -> Self {
+        Self {
+            chain,
+            offset: AtomicI64::new(0),
+        }
+    }
+}
+
+impl Clock for ChainClock {
+    fn now(&self) -> Duration {
+        let wall = SystemClock.now().as_millis() as i64;
+        Duration::from_millis((wall + self.offset.load(Ordering::Relaxed)).max(0) as u64)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+
+    /// Re-measures the drift against the sysvar. Keeps the previous
+    /// correction when the chain cannot be reached.
+    fn sync(&self) {
+        match self.chain.get_clock() {
+            Ok(clock) => {
+                let wall = SystemClock.now().as_millis() as i64;
+                let offset = clock.unix_timestamp * 1_000 - wall;
+                self.offset.store(offset, Ordering::Relaxed);
+                tracing::debug!(offset_ms = offset, "clock synced with the chain");
+            }
+            Err(err) => tracing::warn!(error = %err, "could not sync clock with the chain"),
+        }
+    }
+}
+
+/// Simulated time that only moves through [`ManualClock::advance`]. Sleepers
+/// wake up once enough time has been advanced.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Mutex<Duration>,
+    advanced: Condvar,
+}
+
+impl ManualClock {
+    pub fn new(now: Duration) -> Self {
+        Self {
+            now: Mutex::new(now),
+            advanced: Condvar::new(),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+        self.advanced.notify_all();
+    }
+
+    pub fn set(&self, now: Duration) {
+        *self.now.lock().unwrap() = now;
+        self.advanced.notify_all();
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let deadline = *self.now.lock().unwrap() + duration;
+        self.sleep_until(deadline);
+    }
+
+    // Checks the deadline under the lock, so that an advance racing with
+    // the caller's own reading of the time is not missed.
+    fn sleep_until(&self, deadline: Duration) {
+        let mut now = self.now.lock().unwrap();
+        while *now < deadline {
+            now = self.advanced.wait(now).unwrap();
+        }
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use drillx::Solution;
+use ore_api::consts::{BUS_ADDRESSES, EPOCH_DURATION, MINT_ADDRESS, ONE_MINUTE, TOLERANCE};
+use ore_api::error::OreError;
+use ore_api::instruction::{ClaimArgs, MineArgs, OreInstruction, StakeArgs};
+use ore_api::state::{Bus, Config, Proof};
+use solana_client::client_error::Result as ClientResult;
+use solana_sdk::{
+    clock::Clock as ClockSysvar,
+    hash::Hash,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    keccak::hashv,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::{self, Transaction, TransactionError},
+};
+use spl_associated_token_account::get_associated_token_address;
+
+use crate::chain::{custom_error, Chain, Simulation};
+use crate::clock::{Clock, ManualClock};
+use crate::{proof_pubkey, Challenge};
+
+/// Reward of a solution at `min_difficulty`, doubled for every extra bit.
+const DEFAULT_BASE_REWARD: u64 = 1_000;
+
+/// SOL held by an account whose balance was never set.
+const DEFAULT_LAMPORTS: u64 = 10 * LAMPORTS_PER_SOL;
+
+/// Fee charged to the payer for every signature of a transaction.
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5_000;
+
+/// In-memory chain running a simplified ORE program, so that the master and
+/// nodes can be exercised without a cluster.
+///
+/// Time is read from a [`ManualClock`] and only moves when it is advanced,
+/// directly or through [`FakeChain::advance`]. A `mine` instruction is
+/// checked against the proof's challenge, the minimum difficulty and the
+/// spam window, then rotates the challenge, stamps `last_hash_at` and
+/// credits `base_reward << (difficulty - min_difficulty)`, plus the stake
+/// multiplier once the config has a `top_balance`, less the liveness
+/// penalty when it comes more than `TOLERANCE` seconds late. Program epochs
+/// only end, and need a `reset`, when the config's `last_reset_at` is set.
+/// An `open` instruction creates the signer's proof, and `stake` and `claim`
+/// move ORE between a proof and associated token accounts, which exist as
+/// soon as something is credited to them. Every other instruction is
+/// accepted and ignored.
+///
+/// Every transaction costs its fee payer [`LAMPORTS_PER_SIGNATURE`] per
+/// signature, whether it succeeds or not, and fails without executing when
+/// the payer cannot afford it.
+#[derive(Debug)]
+pub struct FakeChain {
+    clock: Arc<ManualClock>,
+    // when the chain started, to derive slots from the clock
+    genesis: Duration,
+    inner: Mutex<FakeState>,
+}
+
+#[derive(Debug, Clone)]
+struct FakeState {
+    // keyed by proof address
+    proofs: HashMap<Pubkey, Proof>,
+    config: Config,
+    // ORE held by associated token accounts, keyed by their address
+    tokens: HashMap<Pubkey, u64>,
+    // SOL of the accounts whose balance changed from the default
+    lamports: HashMap<Pubkey, u64>,
+    transactions: Vec<Transaction>,
+    statuses: HashMap<Signature, transaction::Result<()>>,
+}
+
+impl FakeChain {
+    /// A chain whose clock starts at `unix_timestamp`.
+    pub fn new(unix_timestamp: i64) -> Self {
+        let now = Duration::from_secs(unix_timestamp as u64);
+        Self::with_clock(Arc::new(ManualClock::new(now)))
+    }
+
+    /// A chain following `clock`, shared with the rest of a simulation.
+    pub fn with_clock(clock: Arc<ManualClock>) -> Self {
+        Self {
+            genesis: clock.now(),
+            clock,
+            inner: Mutex::new(FakeState {
+                proofs: HashMap::new(),
+                tokens: HashMap::new(),
+                lamports: HashMap::new(),
+                config: Config {
+                    base_reward_rate: DEFAULT_BASE_REWARD,
+                    // no program epochs until it is set
+                    last_reset_at: 0,
+                    min_difficulty: 0,
+                    top_balance: 0,
+                },
+                transactions: Vec::new(),
+                statuses: HashMap::new(),
+            }),
+        }
+    }
+
+    pub fn clock(&self) -> Arc<ManualClock> {
+        self.clock.clone()
+    }
+
+    /// Opens a proof for `authority` with the given first challenge, as if
+    /// it had just been reset.
+    pub fn open_proof(&self, authority: Pubkey, challenge: Challenge) -> Proof {
+        let now = self.unix_timestamp();
+        let mut state = self.inner.lock().unwrap();
+        let proof = Proof {
+            authority,
+            balance: 0,
+            challenge,
+            last_hash: [0; 32],
+            last_hash_at: now,
+            last_stake_at: now,
+            miner: authority,
+            total_hashes: 0,
+            total_rewards: 0,
+        };
+        state.proofs.insert(proof_pubkey(authority), proof);
+        proof
+    }
+
+    pub fn set_proof(&self, proof: Proof) {
+        self.inner
+            .lock()
+            .unwrap()
+            .proofs
+            .insert(proof_pubkey(proof.authority), proof);
+    }
+
+    pub fn proof(&self, authority: Pubkey) -> Option<Proof> {
+        self.inner
+            .lock()
+            .unwrap()
+            .proofs
+            .get(&proof_pubkey(authority))
+            .copied()
+    }
+
+    /// ORE held by the associated token account of `owner`.
+    pub fn token_balance(&self, owner: Pubkey) -> u64 {
+        let address = get_associated_token_address(&owner, &MINT_ADDRESS);
+        self.inner
+            .lock()
+            .unwrap()
+            .tokens
+            .get(&address)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_token_balance(&self, owner: Pubkey, amount: u64) {
+        let address = get_associated_token_address(&owner, &MINT_ADDRESS);
+        self.inner.lock().unwrap().tokens.insert(address, amount);
+    }
+
+    /// SOL held by `address`, in lamports.
+    pub fn lamports(&self, address: Pubkey) -> u64 {
+        self.inner.lock().unwrap().lamports(address)
+    }
+
+    pub fn set_lamports(&self, address: Pubkey, lamports: u64) {
+        self.inner
+            .lock()
+            .unwrap()
+            .lamports
+            .insert(address, lamports);
+    }
+
+    pub fn unix_timestamp(&self) -> i64 {
+        self.clock.unix_timestamp()
+    }
+
+    /// Moves the chain's clock forward.
+    pub fn advance(&self, seconds: u64) {
+        self.clock.advance(Duration::from_secs(seconds));
+    }
+
+    pub fn set_min_difficulty(&self, min_difficulty: u32) {
+        self.inner.lock().unwrap().config.min_difficulty = min_difficulty as u64;
+    }
+
+    /// Replaces the config account. `min_difficulty` and `base_reward_rate`
+    /// drive mining, and a non-zero `last_reset_at` has mining fail with
+    /// `NeedsReset` once the program epoch is over, until a `reset`
+    /// instruction lands. A non-zero `top_balance` turns on the stake
+    /// multiplier.
+    pub fn set_config(&self, config: Config) {
+        self.inner.lock().unwrap().config = config;
+    }
+
+    pub fn set_base_reward(&self, base_reward: u64) {
+        self.inner.lock().unwrap().config.base_reward_rate = base_reward;
+    }
+
+    /// Every transaction received, in order, including the failed ones.
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.inner.lock().unwrap().transactions.clone()
+    }
+
+    /// Instructions of every transaction that executed successfully.
+    pub fn landed_instructions(&self) -> Vec<Instruction> {
+        let state = self.inner.lock().unwrap();
+        state
+            .transactions
+            .iter()
+            .filter(|tx| matches!(state.statuses.get(&tx.signatures[0]), Some(Ok(()))))
+            .flat_map(|tx| {
+                let message = &tx.message;
+                message.instructions.iter().map(|ix| Instruction {
+                    program_id: message.account_keys[ix.program_id_index as usize],
+                    accounts: ix
+                        .accounts
+                        .iter()
+                        .map(|index| {
+                            let index = *index as usize;
+                            AccountMeta {
+                                pubkey: message.account_keys[index],
+                                is_signer: message.is_signer(index),
+                                is_writable: message.is_writable(index),
+                            }
+                        })
+                        .collect(),
+                    data: ix.data.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl FakeState {
+    fn lamports(&self, address: Pubkey) -> u64 {
+        self.lamports
+            .get(&address)
+            .copied()
+            .unwrap_or(DEFAULT_LAMPORTS)
+    }
+
+    /// Takes the fee from the payer, then runs the transaction.
+    fn process(&mut self, tx: &Transaction, now: i64) -> transaction::Result<()> {
+        let payer = tx.message.account_keys[0];
+        let fee = LAMPORTS_PER_SIGNATURE * tx.signatures.len() as u64;
+        let balance = self
+            .lamports(payer)
+            .checked_sub(fee)
+            .ok_or(TransactionError::InsufficientFundsForFee)?;
+        self.lamports.insert(payer, balance);
+        self.execute(tx, now)
+    }
+
+    /// Runs the instructions on a copy of the proofs and token balances,
+    /// which replaces the current ones only if all of them succeed.
+    fn execute(&mut self, tx: &Transaction, now: i64) -> transaction::Result<()> {
+        let mut proofs = self.proofs.clone();
+        let mut tokens = self.tokens.clone();
+        let mut config = self.config;
+        let message = &tx.message;
+        for (index, ix) in message.instructions.iter().enumerate() {
+            let program_id = message.account_keys[ix.program_id_index as usize];
+            if program_id != ore_api::ID {
+                continue;
+            }
+            let account = |position: usize| {
+                ix.accounts
+                    .get(position)
+                    .map(|account| message.account_keys[*account as usize])
+            };
+            let result = match ix.data.first().map(|tag| OreInstruction::try_from(*tag)) {
+                Some(Ok(OreInstruction::Mine)) => {
+                    Self::mine(&config, &mut proofs, account(3), &ix.data[1..], now)
+                }
+                Some(Ok(OreInstruction::Reset)) => {
+                    Self::reset(&mut config, now);
+                    Ok(())
+                }
+                Some(Ok(OreInstruction::Open)) => {
+                    Self::open(&mut proofs, account(0), account(1), account(3), now)
+                }
+                Some(Ok(OreInstruction::Stake)) => Self::stake(
+                    &mut proofs,
+                    &mut tokens,
+                    account(1),
+                    account(2),
+                    &ix.data[1..],
+                    now,
+                ),
+                Some(Ok(OreInstruction::Claim)) => Self::claim(
+                    &mut proofs,
+                    &mut tokens,
+                    account(2),
+                    account(1),
+                    &ix.data[1..],
+                ),
+                _ => Ok(()),
+            };
+            result.map_err(|err| TransactionError::InstructionError(index as u8, err))?;
+        }
+        self.proofs = proofs;
+        self.tokens = tokens;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Starts a new program epoch if the current one is over.
+    fn reset(config: &mut Config, now: i64) {
+        if config.last_reset_at > 0 && config.last_reset_at + EPOCH_DURATION <= now {
+            config.last_reset_at = now;
+        }
+    }
+
+    /// Moves `amount` from the sender's token account to the proof.
+    fn stake(
+        proofs: &mut HashMap<Pubkey, Proof>,
+        tokens: &mut HashMap<Pubkey, u64>,
+        proof_address: Option<Pubkey>,
+        sender: Option<Pubkey>,
+        data: &[u8],
+        now: i64,
+    ) -> Result<(), InstructionError> {
+        let args = StakeArgs::try_from_bytes(data)
+            .map_err(|_| InstructionError::InvalidInstructionData)?;
+        let amount = u64::from_le_bytes(args.amount);
+        let proof = proof_address
+            .and_then(|address| proofs.get_mut(&address))
+            .ok_or(InstructionError::InvalidAccountData)?;
+        let balance = sender
+            .and_then(|address| tokens.get_mut(&address))
+            .ok_or(InstructionError::InvalidAccountData)?;
+        *balance = balance
+            .checked_sub(amount)
+            .ok_or(InstructionError::InsufficientFunds)?;
+        proof.balance += amount;
+        proof.last_stake_at = now;
+        Ok(())
+    }
+
+    /// Moves `amount` from the proof to the beneficiary's token account.
+    fn claim(
+        proofs: &mut HashMap<Pubkey, Proof>,
+        tokens: &mut HashMap<Pubkey, u64>,
+        proof_address: Option<Pubkey>,
+        beneficiary: Option<Pubkey>,
+        data: &[u8],
+    ) -> Result<(), InstructionError> {
+        let args = ClaimArgs::try_from_bytes(data)
+            .map_err(|_| InstructionError::InvalidInstructionData)?;
+        let amount = u64::from_le_bytes(args.amount);
+        let proof = proof_address
+            .and_then(|address| proofs.get_mut(&address))
+            .ok_or(InstructionError::InvalidAccountData)?;
+        let beneficiary = beneficiary.ok_or(InstructionError::NotEnoughAccountKeys)?;
+        proof.balance = proof
+            .balance
+            .checked_sub(amount)
+            .ok_or(InstructionError::Custom(OreError::ClaimTooLarge.into()))?;
+        *tokens.entry(beneficiary).or_default() += amount;
+        Ok(())
+    }
+
+    fn open(
+        proofs: &mut HashMap<Pubkey, Proof>,
+        signer: Option<Pubkey>,
+        miner: Option<Pubkey>,
+        proof_address: Option<Pubkey>,
+        now: i64,
+    ) -> Result<(), InstructionError> {
+        let (Some(authority), Some(miner), Some(proof_address)) = (signer, miner, proof_address)
+        else {
+            return Err(InstructionError::NotEnoughAccountKeys);
+        };
+        if proof_address != proof_pubkey(authority) {
+            return Err(InstructionError::InvalidSeeds);
+        }
+        if proofs.contains_key(&proof_address) {
+            return Err(InstructionError::AccountAlreadyInitialized);
+        }
+        proofs.insert(
+            proof_address,
+            Proof {
+                authority,
+                balance: 0,
+                challenge: hashv(&[authority.as_ref(), &now.to_le_bytes()]).0,
+                last_hash: [0; 32],
+                last_hash_at: now,
+                last_stake_at: now,
+                miner,
+                total_hashes: 0,
+                total_rewards: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn mine(
+        config: &Config,
+        proofs: &mut HashMap<Pubkey, Proof>,
+        proof_address: Option<Pubkey>,
+        data: &[u8],
+        now: i64,
+    ) -> Result<(), InstructionError> {
+        let args =
+            MineArgs::try_from_bytes(data).map_err(|_| InstructionError::InvalidInstructionData)?;
+        let proof = proof_address
+            .and_then(|address| proofs.get_mut(&address))
+            .ok_or(InstructionError::InvalidAccountData)?;
+        if config.last_reset_at > 0 && config.last_reset_at + EPOCH_DURATION <= now {
+            return Err(InstructionError::Custom(OreError::NeedsReset.into()));
+        }
+        if now < proof.last_hash_at + ONE_MINUTE - TOLERANCE {
+            return Err(InstructionError::Custom(OreError::Spam.into()));
+        }
+        let solution = Solution::new(args.digest, args.nonce);
+        if !solution.is_valid(&proof.challenge) {
+            return Err(InstructionError::Custom(OreError::HashInvalid.into()));
+        }
+        let hash = solution.to_hash();
+        let difficulty = hash.difficulty() as u64;
+        let min_difficulty = config.min_difficulty;
+        if difficulty < min_difficulty {
+            return Err(InstructionError::Custom(OreError::HashTooEasy.into()));
+        }
+        let mut reward = config
+            .base_reward_rate
+            .saturating_mul(1 << (difficulty - min_difficulty).min(32));
+        // Stake multiplier: up to twice the reward for a proof holding
+        // `top_balance`, unless it staked less than a minute ago.
+        if proof.balance > 0 && config.top_balance > 0 && proof.last_stake_at + ONE_MINUTE < now {
+            let bonus = reward as u128 * proof.balance.min(config.top_balance) as u128
+                / config.top_balance as u128;
+            reward = reward.saturating_add(bonus as u64);
+        }
+        // Late submissions lose a share of the reward per second past the
+        // tolerance, like the program's liveness penalty.
+        let t_liveness = proof.last_hash_at + ONE_MINUTE + TOLERANCE;
+        if now > t_liveness {
+            reward = reward.saturating_sub(
+                reward.saturating_mul((now - t_liveness) as u64) / ONE_MINUTE as u64,
+            );
+        }
+        proof.challenge = hashv(&[&hash.h, &proof.challenge]).0;
+        proof.last_hash = hash.h;
+        proof.last_hash_at = now;
+        proof.total_hashes += 1;
+        proof.total_rewards += reward;
+        proof.balance += reward;
+        Ok(())
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl Chain for FakeChain {
+    fn get_proof(&self, authority: Pubkey) -> ClientResult<Option<Proof>> {
+        Ok(self.proof(authority))
+    }
+
+    fn get_clock(&self) -> ClientResult<ClockSysvar> {
+        let now = self.clock.now();
+        Ok(ClockSysvar {
+            // 400ms slots
+            slot: (now.saturating_sub(self.genesis).as_millis() / 400) as u64,
+            unix_timestamp: now.as_secs() as i64,
+            ..Default::default()
+        })
+    }
+
+    fn get_config(&self) -> ClientResult<Config> {
+        Ok(self.inner.lock().unwrap().config)
+    }
+
+    fn get_buses(&self) -> ClientResult<Vec<Bus>> {
+        Ok((0..BUS_ADDRESSES.len() as u64)
+            .map(|id| Bus {
+                id,
+                rewards: u64::MAX,
+                theoretical_rewards: 0,
+                top_balance: 0,
+            })
+            .collect())
+    }
+
+    fn get_token_balance(&self, owner: Pubkey) -> ClientResult<u64> {
+        Ok(self.token_balance(owner))
+    }
+
+    fn get_balance(&self, address: Pubkey) -> ClientResult<u64> {
+        Ok(self.lamports(address))
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Ok(Hash::new_unique())
+    }
+
+    fn send_transaction(&self, tx: &Transaction) -> ClientResult<Signature> {
+        tx.verify().map_err(|err| custom_error(err.to_string()))?;
+        let signature = tx.signatures[0];
+        let now = self.unix_timestamp();
+        let mut state = self.inner.lock().unwrap();
+        // Like a real cluster, a transaction only executes once.
+        if !state.statuses.contains_key(&signature) {
+            let status = state.process(tx, now);
+            state.statuses.insert(signature, status);
+            state.transactions.push(tx.clone());
+        }
+        Ok(signature)
+    }
+
+    /// Runs `tx` on a copy of the state. The fake program does not meter
+    /// compute nor log.
+    fn simulate_transaction(&self, tx: &Transaction) -> ClientResult<Simulation> {
+        let now = self.unix_timestamp();
+        let mut state = self.inner.lock().unwrap().clone();
+        Ok(Simulation {
+            result: state.process(tx, now),
+            units_consumed: None,
+            logs: vec![],
+        })
+    }
+
+    fn confirm_transaction(
+        &self,
+        signature: &Signature,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        Ok(self.inner.lock().unwrap().statuses.get(signature).cloned())
+    }
+}
@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ore_api::state::{Bus, Config, Proof};
+use solana_client::client_error::Result as ClientResult;
+use solana_sdk::{
+    clock::Clock,
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::{self, Transaction},
+};
+
+use crate::chain::{custom_error, Chain, Simulation};
+
+/// Endpoint taking bundles of transactions, like a Jito block engine,
+/// instead of single transactions.
+#[allow(clippy::result_large_err)]
+pub trait BundleSender: Send + Sync {
+    /// Sends `bundle` and returns its id.
+    fn send_bundle(&self, bundle: &[Transaction]) -> ClientResult<String>;
+}
+
+/// Stand-in for a bundle endpoint, which sends the transactions of every
+/// bundle through `chain`, in order. Unlike a block engine, it does not land
+/// a bundle atomically.
+pub struct LocalBundles {
+    chain: Arc<dyn Chain>,
+    bundles: Mutex<Vec<Vec<Signature>>>,
+}
+
+impl LocalBundles {
+    pub fn new(chain: Arc<dyn Chain>) -> Self {
+        Self {
+            chain,
+            bundles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Signatures of every bundle received, in order.
+    pub fn bundles(&self) -> Vec<Vec<Signature>> {
+        self.bundles.lock().unwrap().clone()
+    }
+}
+
+#[allow(clippy::result_large_err)]
+impl BundleSender for LocalBundles {
+    fn send_bundle(&self, bundle: &[Transaction]) -> ClientResult<String> {
+        let signatures = bundle
+            .iter()
+            .map(|tx| self.chain.send_transaction(tx))
+            .collect::<ClientResult<Vec<_>>>()?;
+        let id = signatures
+            .first()
+            .map(|signature| signature.to_string())
+            .unwrap_or_default();
+        self.bundles.lock().unwrap().push(signatures);
+        Ok(id)
+    }
+}
+
+/// How long a bundled transaction is remembered. Its blockhash expires well
+/// before that, so it cannot be sent again afterwards.
+const BUNDLED_TTL: Duration = Duration::from_secs(120);
+
+/// Requests that can wait for an endpoint's worker. Past that, the endpoint
+/// is left out of the request rather than falling further behind.
+const WORKER_QUEUE: usize = 16;
+
+/// How long to wait for a better answer from the other endpoints once one
+/// has answered.
+const ANSWER_GRACE: Duration = Duration::from_millis(100);
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Thread making the requests to one endpoint, one at a time, so that polling
+/// for a confirmation does not start threads.
+struct Worker {
+    tasks: Mutex<mpsc::SyncSender<Task>>,
+}
+
+impl Worker {
+    fn spawn() -> Self {
+        let (tasks, rx) = mpsc::sync_channel::<Task>(WORKER_QUEUE);
+        // Stops once the fanout, and with it the sender, is dropped.
+        thread::spawn(move || {
+            for task in rx {
+                task();
+            }
+        });
+        Self {
+            tasks: Mutex::new(tasks),
+        }
+    }
+
+    /// Queues `task`, unless the worker is too far behind.
+    fn try_run(&self, task: Task) -> bool {
+        self.tasks.lock().unwrap().try_send(task).is_ok()
+    }
+}
+
+/// Sends every transaction through several endpoints at once, to improve
+/// the odds of landing it when the cluster is congested, and confirms it
+/// through whichever endpoint sees it first. Everything else is read from
+/// the primary endpoint.
+///
+/// The endpoints all get the same signed transaction, so it lands at most
+/// once however many of them forward it.
+pub struct FanoutChain {
+    primary: Arc<dyn Chain>,
+    // the primary first, each with its worker
+    endpoints: Vec<(Arc<dyn Chain>, Worker)>,
+    bundles: Option<(Arc<dyn BundleSender>, Worker)>,
+    // when each transaction was bundled, so that retries are not bundled
+    // again; forgotten once confirmed or after BUNDLED_TTL
+    bundled: Mutex<HashMap<Signature, Instant>>,
+    bundled_ttl: Duration,
+}
+
+impl FanoutChain {
+    pub fn new(primary: Arc<dyn Chain>) -> Self {
+        Self {
+            endpoints: vec![(primary.clone(), Worker::spawn())],
+            primary,
+            bundles: None,
+            bundled: Mutex::new(HashMap::new()),
+            bundled_ttl: BUNDLED_TTL,
+        }
+    }
+
+    /// Also sends and confirms transactions through `endpoint`.
+    pub fn with_endpoint(mut self, endpoint: Arc<dyn Chain>) -> Self {
+        self.endpoints.push((endpoint, Worker::spawn()));
+        self
+    }
+
+    /// Also sends every transaction to `bundles`, once, as a bundle of its
+    /// own.
+    pub fn with_bundles(mut self, bundles: Arc<dyn BundleSender>) -> Self {
+        self.bundles = Some((bundles, Worker::spawn()));
+        self
+    }
+
+    /// Whether `signature` still has to be bundled, remembering that it is.
+    fn needs_bundle(&self, signature: Signature) -> bool {
+        let now = Instant::now();
+        let mut bundled = self.bundled.lock().unwrap();
+        bundled.retain(|_, at| now.duration_since(*at) < self.bundled_ttl);
+        bundled.insert(signature, now).is_none()
+    }
+}
+
+type Job<T> = Box<dyn FnOnce() -> ClientResult<T> + Send>;
+
+/// Runs every job on its worker and returns the first answer `wanted`
+/// accepts, without waiting for the others. Failing that, returns any
+/// successful answer, or the first error, once every endpoint has answered
+/// or [`ANSWER_GRACE`] after the first answer, so that a hung endpoint does
+/// not hold up the others.
+#[allow(clippy::result_large_err)]
+fn race<T: Send + 'static>(
+    jobs: Vec<(&Worker, Job<T>)>,
+    wanted: impl Fn(&T) -> bool,
+) -> ClientResult<T> {
+    let (tx, rx) = mpsc::channel();
+    for (worker, job) in jobs {
+        let tx = tx.clone();
+        if !worker.try_run(Box::new(move || {
+            let _ = tx.send(job());
+        })) {
+            tracing::debug!("endpoint busy, skipped");
+        }
+    }
+    drop(tx);
+    let mut fallback = None;
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let result = match deadline {
+            None => rx.recv().ok(),
+            Some(deadline) => rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .ok(),
+        };
+        let Some(result) = result else {
+            break;
+        };
+        match result {
+            Ok(value) if wanted(&value) => return Ok(value),
+            Ok(value) => fallback = Some(Ok(value)),
+            Err(err) => {
+                tracing::debug!(error = %err, "endpoint failed");
+                if fallback.is_none() {
+                    fallback = Some(Err(err));
+                }
+            }
+        }
+        deadline.get_or_insert_with(|| Instant::now() + ANSWER_GRACE);
+    }
+    fallback.unwrap_or_else(|| Err(custom_error("no endpoint to send to")))
+}
+
+#[allow(clippy::result_large_err)]
+impl Chain for FanoutChain {
+    fn get_proof(&self, authority: Pubkey) -> ClientResult<Option<Proof>> {
+        self.primary.get_proof(authority)
+    }
+
+    fn get_clock(&self) -> ClientResult<Clock> {
+        self.primary.get_clock()
+    }
+
+    fn get_config(&self) -> ClientResult<Config> {
+        self.primary.get_config()
+    }
+
+    fn get_buses(&self) -> ClientResult<Vec<Bus>> {
+        self.primary.get_buses()
+    }
+
+    fn get_token_balance(&self, owner: Pubkey) -> ClientResult<u64> {
+        self.primary.get_token_balance(owner)
+    }
+
+    fn get_balance(&self, address: Pubkey) -> ClientResult<u64> {
+        self.primary.get_balance(address)
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        self.primary.get_latest_blockhash()
+    }
+
+    fn send_transaction(&self, tx: &Transaction) -> ClientResult<Signature> {
+        let signature = tx.signatures[0];
+        let mut jobs: Vec<(&Worker, Job<Signature>)> = self
+            .endpoints
+            .iter()
+            .map(|(endpoint, worker)| {
+                let (endpoint, tx) = (endpoint.clone(), tx.clone());
+                (
+                    worker,
+                    Box::new(move || endpoint.send_transaction(&tx)) as Job<Signature>,
+                )
+            })
+            .collect();
+        if let Some((bundles, worker)) = &self.bundles {
+            if self.needs_bundle(signature) {
+                let (bundles, tx) = (bundles.clone(), tx.clone());
+                jobs.push((
+                    worker,
+                    Box::new(move || {
+                        let id = bundles.send_bundle(&[tx])?;
+                        tracing::debug!(bundle = %id, %signature, "bundle sent");
+                        Ok(signature)
+                    }),
+                ));
+            }
+        }
+        race(jobs, |sent| *sent == signature)
+    }
+
+    fn simulate_transaction(&self, tx: &Transaction) -> ClientResult<Simulation> {
+        self.primary.simulate_transaction(tx)
+    }
+
+    fn confirm_transaction(
+        &self,
+        signature: &Signature,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        let signature = *signature;
+        // Gone once this poll returns, so that polls still queued behind a
+        // hung request are not made for nothing.
+        let polling = Arc::new(());
+        let jobs = self
+            .endpoints
+            .iter()
+            .map(|(endpoint, worker)| {
+                let (endpoint, polling) = (endpoint.clone(), Arc::downgrade(&polling));
+                (
+                    worker,
+                    Box::new(move || match polling.upgrade() {
+                        Some(_) => endpoint.confirm_transaction(&signature),
+                        None => Ok(None),
+                    }) as Job<Option<transaction::Result<()>>>,
+                )
+            })
+            .collect();
+        let status = race(jobs, Option::is_some)?;
+        if status.is_some() {
+            self.bundled.lock().unwrap().remove(&signature);
+        }
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_chain::FakeChain;
+    use crate::miner::ensure_proof;
+    use solana_client::rpc_client::RpcClient;
+    use solana_sdk::{signature::Keypair, signer::Signer};
+
+    const START: i64 = 1_700_000_000;
+
+    fn open(fanout: &FanoutChain, keypair: &Keypair) -> Transaction {
+        let authority = keypair.pubkey();
+        Transaction::new_signed_with_payer(
+            &[ore_api::instruction::open(authority, authority, authority)],
+            Some(&authority),
+            &[keypair],
+            fanout.get_latest_blockhash().unwrap(),
+        )
+    }
+
+    fn wait_for_bundles(bundles: &LocalBundles, count: usize) {
+        let started = Instant::now();
+        while bundles.bundles().len() < count {
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "no bundle sent"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn transactions_fan_out_to_every_endpoint() {
+        let chain = Arc::new(FakeChain::new(START));
+        let bundles = Arc::new(LocalBundles::new(chain.clone()));
+        // Nothing listens there.
+        let down = Arc::new(RpcClient::new("http://127.0.0.1:1".to_string()));
+        let fanout = FanoutChain::new(chain.clone())
+            .with_endpoint(down)
+            .with_endpoint(chain.clone())
+            .with_bundles(bundles.clone());
+
+        let keypair = Keypair::new();
+        let tx = open(&fanout, &keypair);
+        let signature = tx.signatures[0];
+        assert_eq!(fanout.send_transaction(&tx).unwrap(), signature);
+        // Retried before it is confirmed: the bundle is not sent again.
+        assert_eq!(fanout.send_transaction(&tx).unwrap(), signature);
+        assert_eq!(
+            fanout.confirm_transaction(&signature).unwrap(),
+            Some(Ok(()))
+        );
+        wait_for_bundles(&bundles, 1);
+        assert_eq!(bundles.bundles(), vec![vec![signature]]);
+        // Sent every way, landed once.
+        assert_eq!(chain.transactions().len(), 1);
+        assert!(chain.proof(keypair.pubkey()).is_some());
+        assert!(fanout.bundled.lock().unwrap().is_empty());
+
+        // The whole send path works through it.
+        let keypair = Keypair::new();
+        let proof = ensure_proof(&fanout, &keypair, &keypair).unwrap();
+        assert_eq!(chain.proof(keypair.pubkey()), Some(proof));
+
+        // Nothing lands when every endpoint is down.
+        let down = FanoutChain::new(Arc::new(RpcClient::new("http://127.0.0.1:1".to_string())));
+        assert!(down.send_transaction(&tx).is_err());
+    }
+
+    /// Endpoint whose requests never answer, until it is dropped.
+    struct Hung {
+        released: Mutex<mpsc::Receiver<()>>,
+    }
+
+    impl Hung {
+        fn new() -> (Self, mpsc::Sender<()>) {
+            let (release, released) = mpsc::channel();
+            let hung = Self {
+                released: Mutex::new(released),
+            };
+            (hung, release)
+        }
+
+        #[allow(clippy::result_large_err)]
+        fn hang<T>(&self) -> ClientResult<T> {
+            let _ = self.released.lock().unwrap().recv();
+            Err(custom_error("released"))
+        }
+    }
+
+    impl Chain for Hung {
+        fn get_proof(&self, _: Pubkey) -> ClientResult<Option<Proof>> {
+            self.hang()
+        }
+
+        fn get_clock(&self) -> ClientResult<Clock> {
+            self.hang()
+        }
+
+        fn get_config(&self) -> ClientResult<Config> {
+            self.hang()
+        }
+
+        fn get_buses(&self) -> ClientResult<Vec<Bus>> {
+            self.hang()
+        }
+
+        fn get_token_balance(&self, _: Pubkey) -> ClientResult<u64> {
+            self.hang()
+        }
+
+        fn get_balance(&self, _: Pubkey) -> ClientResult<u64> {
+            self.hang()
+        }
+
+        fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+            self.hang()
+        }
+
+        fn send_transaction(&self, _: &Transaction) -> ClientResult<Signature> {
+            self.hang()
+        }
+
+        fn simulate_transaction(&self, _: &Transaction) -> ClientResult<Simulation> {
+            self.hang()
+        }
+
+        fn confirm_transaction(
+            &self,
+            _: &Signature,
+        ) -> ClientResult<Option<transaction::Result<()>>> {
+            self.hang()
+        }
+    }
+
+    #[test]
+    fn a_hung_endpoint_does_not_hold_up_confirmations() {
+        let chain = Arc::new(FakeChain::new(START));
+        let (hung, _release) = Hung::new();
+        let fanout = FanoutChain::new(chain.clone()).with_endpoint(Arc::new(hung));
+
+        let keypair = Keypair::new();
+        let tx = open(&fanout, &keypair);
+        let signature = tx.signatures[0];
+        let started = Instant::now();
+        // More polls than the hung endpoint's worker can queue.
+        for _ in 0..2 * WORKER_QUEUE {
+            assert_eq!(fanout.confirm_transaction(&signature).unwrap(), None);
+        }
+        assert_eq!(fanout.send_transaction(&tx).unwrap(), signature);
+        assert_eq!(
+            fanout.confirm_transaction(&signature).unwrap(),
+            Some(Ok(()))
+        );
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "took {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn unconfirmed_bundles_are_forgotten() {
+        let chain = Arc::new(FakeChain::new(START));
+        let bundles = Arc::new(LocalBundles::new(chain.clone()));
+        let mut fanout = FanoutChain::new(chain.clone()).with_bundles(bundles.clone());
+        fanout.bundled_ttl = Duration::ZERO;
+
+        // Never confirmed, so only the next send clears them.
+        for _ in 0..3 {
+            fanout
+                .send_transaction(&open(&fanout, &Keypair::new()))
+                .unwrap();
+        }
+        wait_for_bundles(&bundles, 3);
+        assert_eq!(fanout.bundled.lock().unwrap().len(), 1);
+    }
+}
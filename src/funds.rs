@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::chain::Chain;
+use crate::clock::Clock;
+use crate::metrics::Metrics;
+use crate::status::{FeePayerStatus, PoolStatus};
+
+/// How often the balance is read in between submissions.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Watches the SOL balance of the account paying the transaction fees, so
+/// that running dry does not go unnoticed: every check updates the metrics
+/// and the status, and warns while the balance is below the minimum.
+#[derive(Clone)]
+pub struct BalanceGuard {
+    chain: Arc<dyn Chain>,
+    fee_payer: Pubkey,
+    min_lamports: u64,
+    pause: bool,
+    metrics: Arc<Metrics>,
+    status: PoolStatus,
+}
+
+impl BalanceGuard {
+    pub fn new(
+        chain: Arc<dyn Chain>,
+        fee_payer: Pubkey,
+        min_lamports: u64,
+        metrics: Arc<Metrics>,
+        status: PoolStatus,
+    ) -> Self {
+        Self {
+            chain,
+            fee_payer,
+            min_lamports,
+            pause: false,
+            metrics,
+            status,
+        }
+    }
+
+    /// Marks the pool as out of funds while the balance is below the
+    /// minimum, so that the master stops accepting work.
+    pub fn with_pause(mut self, pause: bool) -> Self {
+        self.pause = pause;
+        self
+    }
+
+    /// Whether the pool stops accepting work when funds run low.
+    pub fn pauses(&self) -> bool {
+        self.pause
+    }
+
+    /// Reads the balance and returns whether it covers the minimum. The
+    /// previous verdict is kept when it cannot be read.
+    pub fn check(&self) -> bool {
+        let lamports = match self.chain.get_balance(self.fee_payer) {
+            Ok(lamports) => lamports,
+            Err(err) => {
+                tracing::warn!(fee_payer = %self.fee_payer, error = %err, "could not read the fee payer's balance");
+                return !self
+                    .status
+                    .read()
+                    .fee_payer
+                    .is_some_and(|status| status.low);
+            }
+        };
+        let low = lamports < self.min_lamports;
+        self.metrics.fee_payer_lamports.set(lamports as i64);
+        let was_low = self
+            .status
+            .write()
+            .fee_payer
+            .replace(FeePayerStatus {
+                address: self.fee_payer,
+                lamports,
+                low,
+            })
+            .is_some_and(|status| status.low);
+        if low {
+            tracing::warn!(
+                fee_payer = %self.fee_payer,
+                sol = lamports_to_sol(lamports),
+                min_sol = lamports_to_sol(self.min_lamports),
+                paused = self.pause,
+                "fee payer is running out of SOL"
+            );
+        } else if was_low {
+            tracing::info!(
+                fee_payer = %self.fee_payer,
+                sol = lamports_to_sol(lamports),
+                "fee payer was topped up"
+            );
+        }
+        if self.pause {
+            self.status.set_out_of_funds(low);
+        }
+        !low
+    }
+
+    /// Checks the balance every [`CHECK_INTERVAL`], forever.
+    pub fn run(&self, clock: Arc<dyn Clock>) {
+        loop {
+            self.check();
+            clock.sleep(CHECK_INTERVAL);
+        }
+    }
+}
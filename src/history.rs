@@ -0,0 +1,377 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::Challenge;
+
+/// What happened to the mine transaction of an epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionStatus {
+    Confirmed,
+    Failed,
+}
+
+/// One finished epoch of a staking authority, or a failed attempt at
+/// submitting it, as kept in the history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochRecord {
+    #[serde(with = "pubkey_string")]
+    pub staking_authority: Pubkey,
+    #[serde(with = "challenge_hex")]
+    pub challenge: Challenge,
+    /// Unix timestamps, in seconds.
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub solutions: usize,
+    pub contributors: usize,
+    pub best_difficulty: u32,
+    #[serde(with = "pubkey_string")]
+    pub best_submitter: Pubkey,
+    pub signature: Option<String>,
+    pub status: SubmissionStatus,
+    pub error: Option<String>,
+    /// Grains credited to the proof by the submission.
+    pub reward: u64,
+    /// Grains of the reward kept in the proof as stake instead of being
+    /// distributed.
+    #[serde(default)]
+    pub restaked: u64,
+    /// Grains credited to the operator before the miners were paid.
+    #[serde(default)]
+    pub fee: u64,
+}
+
+/// Filters for [`EpochHistory::query`]. Unset fields match everything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HistoryQuery {
+    pub staking_authority: Option<Pubkey>,
+    /// Only epochs that ended at or after this unix timestamp.
+    pub since: Option<u64>,
+    /// Only epochs that ended at or before this unix timestamp.
+    pub until: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    /// Whether `record` passes the filters. `limit` is not taken into account.
+    pub fn matches(&self, record: &EpochRecord) -> bool {
+        self.staking_authority
+            .is_none_or(|authority| authority == record.staking_authority)
+            && self.since.is_none_or(|since| record.ended_at >= since)
+            && self.until.is_none_or(|until| record.ended_at <= until)
+    }
+}
+
+/// Append-only log of finished epochs. Records are kept in memory and, when
+/// opened from a file, appended to it as JSON lines.
+#[derive(Debug, Default)]
+pub struct EpochHistory {
+    records: Vec<EpochRecord>,
+    file: Option<File>,
+}
+
+impl EpochHistory {
+    /// History kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the records already in `path` and appends new ones to it. The
+    /// file is created when missing.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let records = match File::open(path) {
+            Ok(file) => read_jsonl(file)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            records,
+            file: Some(file),
+        })
+    }
+
+    pub fn append(&mut self, record: EpochRecord) {
+        if let Some(file) = &mut self.file {
+            let mut line = serde_json::to_string(&record).unwrap();
+            line.push('\n');
+            if let Err(err) = file.write_all(line.as_bytes()).and_then(|_| file.flush()) {
+                tracing::error!(error = %err, "could not write epoch history");
+            }
+        }
+        self.records.push(record);
+    }
+
+    /// Matching records, most recent first.
+    pub fn query(&self, query: &HistoryQuery) -> Vec<&EpochRecord> {
+        self.records
+            .iter()
+            .rev()
+            .filter(|record| query.matches(record))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+
+    pub fn records(&self) -> &[EpochRecord] {
+        &self.records
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+/// Parses a JSON lines history, skipping blank lines.
+pub fn read_jsonl(reader: impl io::Read) -> io::Result<Vec<EpochRecord>> {
+    let mut records = vec![];
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+    Ok(records)
+}
+
+pub fn write_jsonl<'a>(
+    mut writer: impl Write,
+    records: impl IntoIterator<Item = &'a EpochRecord>,
+) -> io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+pub fn write_csv<'a>(
+    mut writer: impl Write,
+    records: impl IntoIterator<Item = &'a EpochRecord>,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "staking_authority,challenge,started_at,ended_at,solutions,contributors,\
+         best_difficulty,best_submitter,signature,status,error,reward,restaked,\
+         fee"
+    )?;
+    for record in records {
+        let status = match record.status {
+            SubmissionStatus::Confirmed => "confirmed",
+            SubmissionStatus::Failed => "failed",
+        };
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            record.staking_authority,
+            to_hex(&record.challenge),
+            record.started_at,
+            record.ended_at,
+            record.solutions,
+            record.contributors,
+            record.best_difficulty,
+            record.best_submitter,
+            record.signature.as_deref().unwrap_or_default(),
+            status,
+            csv_field(record.error.as_deref().unwrap_or_default()),
+            record.reward,
+            record.restaked,
+            record.fee,
+        )?;
+    }
+    Ok(())
+}
+
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn to_hex(challenge: &Challenge) -> String {
+    challenge
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+mod pubkey_string {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(pubkey)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Pubkey, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        Pubkey::from_str(&value).map_err(D::Error::custom)
+    }
+}
+
+mod challenge_hex {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        challenge: &Challenge,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(challenge))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Challenge, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value.len() != 64 || !value.is_ascii() {
+            return Err(D::Error::custom("challenge must be 64 hex characters"));
+        }
+        let mut challenge = [0; 32];
+        for (byte, pair) in challenge.iter_mut().zip(value.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).unwrap();
+            *byte = u8::from_str_radix(pair, 16).map_err(D::Error::custom)?;
+        }
+        Ok(challenge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(staking_authority: Pubkey, ended_at: u64) -> EpochRecord {
+        EpochRecord {
+            staking_authority,
+            challenge: [0xab; 32],
+            started_at: ended_at - 60,
+            ended_at,
+            solutions: 4,
+            contributors: 2,
+            best_difficulty: 12,
+            best_submitter: Pubkey::new_unique(),
+            signature: Some("sig".to_string()),
+            status: SubmissionStatus::Confirmed,
+            error: None,
+            reward: 1_000,
+            restaked: 0,
+            fee: 0,
+        }
+    }
+
+    fn ended_at(records: Vec<&EpochRecord>) -> Vec<u64> {
+        records.iter().map(|record| record.ended_at).collect()
+    }
+
+    #[test]
+    fn queries_filter_and_return_the_most_recent_first() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut history = EpochHistory::new();
+        for (authority, ended_at) in [(a, 100), (b, 160), (a, 220), (a, 280)] {
+            history.append(record(authority, ended_at));
+        }
+
+        let all = HistoryQuery::default();
+        assert_eq!(ended_at(history.query(&all)), vec![280, 220, 160, 100]);
+        let by_authority = HistoryQuery {
+            staking_authority: Some(b),
+            ..Default::default()
+        };
+        assert_eq!(ended_at(history.query(&by_authority)), vec![160]);
+        let window = HistoryQuery {
+            since: Some(160),
+            until: Some(220),
+            ..Default::default()
+        };
+        assert_eq!(ended_at(history.query(&window)), vec![220, 160]);
+        let limited = HistoryQuery {
+            staking_authority: Some(a),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(ended_at(history.query(&limited)), vec![280, 220]);
+    }
+
+    #[test]
+    fn csv_has_a_line_per_record_and_quotes_errors() {
+        let confirmed = record(Pubkey::new_unique(), 60);
+        let mut failed = record(Pubkey::new_unique(), 120);
+        failed.signature = None;
+        failed.status = SubmissionStatus::Failed;
+        failed.error = Some("custom program error: \"0x1\", retried".to_string());
+
+        let mut csv = vec![];
+        write_csv(&mut csv, [&confirmed, &failed]).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert_eq!(
+            lines[1],
+            format!(
+                "{},{},0,60,4,2,12,{},sig,confirmed,,1000,0,0",
+                confirmed.staking_authority,
+                "ab".repeat(32),
+                confirmed.best_submitter
+            )
+        );
+        assert!(
+            lines[2].ends_with(",,failed,\"custom program error: \"\"0x1\"\", retried\",1000,0,0")
+        );
+    }
+
+    #[test]
+    fn histories_are_reloaded_from_their_file() {
+        let path =
+            std::env::temp_dir().join(format!("drillx-history-{}.jsonl", Pubkey::new_unique()));
+        let records = vec![
+            record(Pubkey::new_unique(), 60),
+            record(Pubkey::new_unique(), 120),
+        ];
+        {
+            let mut history = EpochHistory::open(&path).unwrap();
+            assert!(history.is_empty());
+            for record in &records {
+                history.append(record.clone());
+            }
+        }
+
+        let mut history = EpochHistory::open(&path).unwrap();
+        assert_eq!(history.records(), records.as_slice());
+        // New records are appended after the ones already there.
+        history.append(record(Pubkey::new_unique(), 180));
+        let reloaded = read_jsonl(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reloaded.len(), 3);
+        assert_eq!(&reloaded[..2], records.as_slice());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn jsonl_round_trips_and_skips_blank_lines() {
+        let records = vec![
+            record(Pubkey::new_unique(), 60),
+            record(Pubkey::new_unique(), 120),
+        ];
+        let mut jsonl = vec![];
+        write_jsonl(&mut jsonl, &records).unwrap();
+        jsonl.extend_from_slice(b"\n  \n");
+        assert_eq!(read_jsonl(jsonl.as_slice()).unwrap(), records);
+    }
+}
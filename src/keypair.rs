@@ -0,0 +1,221 @@
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use solana_sdk::bs58;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::keypair::keypair_from_seed_phrase_and_passphrase;
+use solana_sdk::signer::EncodableKey;
+
+/// Environment variable holding a base58 secret key, used when no keypair is
+/// given on the command line.
+pub const KEYPAIR_ENV: &str = "DRILLX_KEYPAIR";
+
+/// Where the Solana CLI keeps its config, relative to the home directory.
+const CLI_CONFIG: &str = ".config/solana/cli/config.yml";
+
+/// Default keypair of the Solana CLI, relative to the home directory.
+const DEFAULT_KEYPAIR: &str = ".config/solana/id.json";
+
+/// Loads a keypair from `source`:
+///
+/// - `stdin` or `-`: a JSON byte array, as written by `solana-keygen`, read
+///   from stdin.
+/// - `prompt:` or `prompt://`: a seed phrase and optional passphrase typed
+///   on the terminal.
+/// - anything else: a JSON keypair file, with a leading `~` expanded.
+///
+/// Without a source, the base58 secret in [`KEYPAIR_ENV`] is used if set,
+/// then the `keypair_path` of the Solana CLI config, then
+/// `~/.config/solana/id.json`.
+pub fn load_keypair(source: Option<&str>) -> Result<Keypair, Box<dyn Error>> {
+    match source {
+        Some("stdin" | "-") => read_json(&mut io::stdin().lock())
+            .map_err(|err| format!("could not read a JSON keypair from stdin: {}", err).into()),
+        Some("prompt:" | "prompt://") => prompt_seed_phrase().map_err(|err| {
+            format!("could not derive a keypair from the seed phrase: {}", err).into()
+        }),
+        Some(path) => read_file(&expand_tilde(path)),
+        None => default_keypair(
+            std::env::var(KEYPAIR_ENV).ok().as_deref(),
+            home_dir().as_deref(),
+        ),
+    }
+}
+
+/// The keypair used without a source: `env`, the value of [`KEYPAIR_ENV`],
+/// if set, then the `keypair_path` of the Solana CLI config under `home`,
+/// then the default keypair under `home`.
+pub fn default_keypair(env: Option<&str>, home: Option<&Path>) -> Result<Keypair, Box<dyn Error>> {
+    if let Some(secret) = env {
+        return from_base58(secret)
+            .map_err(|err| format!("could not read {}: {}", KEYPAIR_ENV, err).into());
+    }
+    let home = home.ok_or("no keypair given and HOME is not set")?;
+    let path = std::fs::read_to_string(home.join(CLI_CONFIG))
+        .ok()
+        .and_then(|config| keypair_path_from_cli_config(&config, Some(home)))
+        .unwrap_or_else(|| home.join(DEFAULT_KEYPAIR));
+    read_file(&path)
+}
+
+fn read_file(path: &Path) -> Result<Keypair, Box<dyn Error>> {
+    Keypair::read_from_file(path)
+        .map_err(|err| format!("could not read keypair {}: {}", path.display(), err).into())
+}
+
+/// Reads a keypair written as a JSON byte array.
+pub fn read_json(reader: &mut impl io::Read) -> Result<Keypair, Box<dyn Error>> {
+    Keypair::read(reader)
+}
+
+/// Decodes a base58 secret key, as exported by wallets.
+pub fn from_base58(secret: &str) -> Result<Keypair, Box<dyn Error>> {
+    let bytes = bs58::decode(secret.trim()).into_vec()?;
+    Ok(Keypair::from_bytes(&bytes)?)
+}
+
+/// Asks for a seed phrase, then a passphrase which may be empty, on the
+/// terminal. Neither is echoed.
+fn prompt_seed_phrase() -> Result<Keypair, Box<dyn Error>> {
+    let seed_phrase = rpassword::prompt_password("seed phrase: ")?;
+    let passphrase = rpassword::prompt_password("passphrase (empty for none): ")?;
+    from_seed_phrase(&seed_phrase, &passphrase)
+}
+
+/// Derives the keypair of a seed phrase as typed, whatever the whitespace
+/// between its words.
+pub fn from_seed_phrase(seed_phrase: &str, passphrase: &str) -> Result<Keypair, Box<dyn Error>> {
+    let seed_phrase = seed_phrase.split_whitespace().collect::<Vec<_>>().join(" ");
+    if seed_phrase.is_empty() {
+        return Err("empty seed phrase".into());
+    }
+    keypair_from_seed_phrase_and_passphrase(&seed_phrase, passphrase)
+}
+
+/// The `keypair_path` of a Solana CLI config file, with a leading `~`
+/// replaced by `home`.
+pub fn keypair_path_from_cli_config(config: &str, home: Option<&Path>) -> Option<PathBuf> {
+    config.lines().find_map(|line| {
+        let path = line.trim().strip_prefix("keypair_path:")?.trim();
+        let path = path.trim_matches(|c| c == '\'' || c == '"');
+        (!path.is_empty()).then(|| expand_home(path, home))
+    })
+}
+
+/// Replaces a leading `~` with the home directory.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    expand_home(path, home_dir().as_deref())
+}
+
+fn expand_home(path: &str, home: Option<&Path>) -> PathBuf {
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signer::Signer;
+
+    use super::*;
+
+    /// A directory of its own under the temporary directory.
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("drillx-keypair-{}", Keypair::new().pubkey()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn keypairs_load_from_base58_and_files() {
+        let keypair = Keypair::new();
+        assert_eq!(from_base58(&keypair.to_base58_string()).unwrap(), keypair);
+        assert!(from_base58("not base58!").is_err());
+
+        let path = temp_dir().join("id.json");
+        std::fs::write(&path, format!("{:?}", keypair.to_bytes())).unwrap();
+        assert_eq!(load_keypair(path.to_str()).unwrap(), keypair);
+        std::fs::remove_file(&path).unwrap();
+        let err = load_keypair(path.to_str()).unwrap_err().to_string();
+        assert!(err.contains(path.to_str().unwrap()), "{}", err);
+    }
+
+    #[test]
+    fn seed_phrases_ignore_extra_whitespace() {
+        let seed_phrase =
+            "pistol note mirror volcano spider maze swamp motion wreck artwork alley hint";
+        let typed = format!("  {}  ", seed_phrase.replace(' ', "   "));
+        assert_eq!(
+            from_seed_phrase(&typed, "secret").unwrap(),
+            keypair_from_seed_phrase_and_passphrase(seed_phrase, "secret").unwrap()
+        );
+        assert!(from_seed_phrase(" ", "").is_err());
+    }
+
+    #[test]
+    fn keypair_paths_are_read_from_the_cli_config() {
+        let config = "json_rpc_url: https://api.devnet.solana.com\n\
+                      keypair_path: /keys/pool.json\n\
+                      commitment: confirmed\n";
+        assert_eq!(
+            keypair_path_from_cli_config(config, None),
+            Some("/keys/pool.json".into())
+        );
+        assert_eq!(
+            keypair_path_from_cli_config("keypair_path: '/keys/quoted.json'", None),
+            Some("/keys/quoted.json".into())
+        );
+        assert_eq!(
+            keypair_path_from_cli_config(
+                "keypair_path: ~/pool.json",
+                Some(Path::new("/home/pool"))
+            ),
+            Some("/home/pool/pool.json".into())
+        );
+        assert_eq!(
+            keypair_path_from_cli_config("commitment: confirmed", None),
+            None
+        );
+    }
+
+    #[test]
+    fn without_a_source_the_environment_then_the_cli_config_are_used() {
+        let home = temp_dir();
+
+        let err = default_keypair(None, Some(&home)).unwrap_err().to_string();
+        assert!(err.contains(DEFAULT_KEYPAIR), "{}", err);
+        assert!(default_keypair(None, None).is_err());
+        let default = Keypair::new();
+        std::fs::create_dir_all(home.join(".config/solana/cli")).unwrap();
+        default.write_to_file(home.join(DEFAULT_KEYPAIR)).unwrap();
+        assert_eq!(default_keypair(None, Some(&home)).unwrap(), default);
+
+        // The CLI config wins over the default path, and may use `~`.
+        let configured = Keypair::new();
+        configured.write_to_file(home.join("pool.json")).unwrap();
+        std::fs::write(home.join(CLI_CONFIG), "keypair_path: ~/pool.json\n").unwrap();
+        assert_eq!(default_keypair(None, Some(&home)).unwrap(), configured);
+
+        // The environment wins over both.
+        let from_env = Keypair::new();
+        let secret = from_env.to_base58_string();
+        assert_eq!(
+            default_keypair(Some(&secret), Some(&home)).unwrap(),
+            from_env
+        );
+        let err = default_keypair(Some("not base58!"), Some(&home))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains(KEYPAIR_ENV), "{}", err);
+
+        std::fs::remove_dir_all(&home).unwrap();
+    }
+}
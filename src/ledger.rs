@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use solana_sdk::pubkey::Pubkey;
+
+/// Shares and rewards credited to a miner authority.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MinerAccount {
+    /// Valid solutions submitted since the master started.
+    pub shares: u64,
+    /// Rewards credited so far, in grains.
+    pub balance: u64,
+}
+
+/// Cut of each epoch's reward kept by the pool operator: `basis_points` of
+/// the reward (1% is 100) plus `fixed` grains, up to the whole reward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatorFee {
+    /// Ledger account the fee is credited to.
+    pub account: Pubkey,
+    pub basis_points: u64,
+    pub fixed: u64,
+}
+
+impl OperatorFee {
+    /// The fee taken out of `reward`.
+    pub fn of(&self, reward: u64) -> u64 {
+        let share = (reward as u128 * self.basis_points.min(10_000) as u128 / 10_000) as u64;
+        share.saturating_add(self.fixed).min(reward)
+    }
+}
+
+/// Off-chain record of what each miner authority is owed from the pool's
+/// proof balance.
+#[derive(Debug, Clone, Default)]
+pub struct RewardLedger {
+    accounts: HashMap<Pubkey, MinerAccount>,
+}
+
+impl RewardLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_share(&mut self, miner_authority: Pubkey) {
+        self.accounts.entry(miner_authority).or_default().shares += 1;
+    }
+
+    /// Splits `reward` between the contributors of an epoch, in proportion
+    /// to the shares each one sent during the epoch. Rounding dust goes to
+    /// the contributor with the most shares. Returns what was credited to
+    /// each miner.
+    pub fn distribute(&mut self, reward: u64, shares: &HashMap<Pubkey, u64>) -> Vec<(Pubkey, u64)> {
+        let total: u128 = shares.values().map(|shares| *shares as u128).sum();
+        if reward == 0 || total == 0 {
+            return vec![];
+        }
+        let mut splits: Vec<(Pubkey, u64)> = shares
+            .iter()
+            .map(|(miner, shares)| (*miner, (reward as u128 * *shares as u128 / total) as u64))
+            .collect();
+        let credited: u64 = splits.iter().map(|(_, amount)| amount).sum();
+        if let Some(top) = shares
+            .iter()
+            .max_by_key(|(miner, shares)| (**shares, **miner))
+            .and_then(|(top, _)| splits.iter().position(|(miner, _)| miner == top))
+        {
+            splits[top].1 += reward - credited;
+        }
+        for (miner, amount) in &splits {
+            self.credit(*miner, *amount);
+        }
+        splits
+    }
+
+    pub fn credit(&mut self, miner_authority: Pubkey, amount: u64) {
+        self.accounts.entry(miner_authority).or_default().balance += amount;
+    }
+
+    pub fn account(&self, miner_authority: &Pubkey) -> MinerAccount {
+        self.accounts
+            .get(miner_authority)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (&Pubkey, &MinerAccount)> {
+        self.accounts.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewards_are_split_by_shares() {
+        let (a, b) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut ledger = RewardLedger::new();
+        let mut splits = ledger.distribute(900, &[(a, 2), (b, 1)].into_iter().collect());
+        splits.sort_by_key(|(_, amount)| *amount);
+        assert_eq!(splits, vec![(b, 300), (a, 600)]);
+        assert_eq!(ledger.account(&a).balance, 600);
+        assert_eq!(ledger.account(&b).balance, 300);
+    }
+
+    #[test]
+    fn dust_goes_to_the_most_shares() {
+        let (a, b, c) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut ledger = RewardLedger::new();
+        ledger.distribute(100, &[(a, 1), (b, 2), (c, 1)].into_iter().collect());
+        assert_eq!(ledger.account(&a).balance, 25);
+        assert_eq!(ledger.account(&b).balance, 50);
+        assert_eq!(ledger.account(&c).balance, 25);
+
+        ledger.distribute(10, &[(a, 1), (b, 2)].into_iter().collect());
+        assert_eq!(ledger.account(&a).balance, 25 + 3);
+        assert_eq!(ledger.account(&b).balance, 50 + 7);
+    }
+
+    #[test]
+    fn nothing_is_credited_without_reward_or_shares() {
+        let a = Pubkey::new_unique();
+        let mut ledger = RewardLedger::new();
+        assert!(ledger
+            .distribute(0, &[(a, 1)].into_iter().collect())
+            .is_empty());
+        assert!(ledger.distribute(100, &HashMap::new()).is_empty());
+        assert_eq!(ledger.account(&a), MinerAccount::default());
+    }
+
+    #[test]
+    fn shares_and_credits_add_up() {
+        let a = Pubkey::new_unique();
+        let mut ledger = RewardLedger::new();
+        ledger.record_share(a);
+        ledger.record_share(a);
+        ledger.credit(a, 5);
+        ledger.credit(a, 7);
+        assert_eq!(
+            ledger.account(&a),
+            MinerAccount {
+                shares: 2,
+                balance: 12
+            }
+        );
+    }
+}
@@ -1,31 +1,78 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use chain::Chain;
+use clock::{Clock, SystemClock};
 use drillx::{Hash, Solution};
-use miner::{find_bus, get_clock, send_and_confirm};
+use funds::BalanceGuard;
+use history::{to_hex, EpochRecord, SubmissionStatus};
+use ledger::OperatorFee;
+use metrics::Metrics;
+use miner::{
+    find_bus, get_clock, send_and_confirm, with_compute_budget, FailureReason, SendOptions,
+};
 use ore_api::consts::{ONE_MINUTE, PROOF};
+use ore_api::error::OreError;
 use ore_api::state::Proof;
-use ore_utils::AccountDeserialize;
 use rand::Rng;
-use solana_rpc_client::rpc_client::RpcClient;
+use registry::{WorkerInfo, WorkerRegistry};
+use rustls::ClientConfig;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
+use status::{AuthorityStatus, PoolStatus};
 use std::collections::HashMap;
-use std::net::{TcpListener, TcpStream};
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::thread::spawn;
-use std::time::Instant;
-use tungstenite::{accept, stream::MaybeTlsStream, Message, WebSocket};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Receiver;
+use tracing::Span;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::{client_tls_with_config, stream::MaybeTlsStream, Connector, Message, WebSocket};
 
+pub mod admission;
+pub mod api;
+pub mod chain;
+pub mod clock;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fake_chain;
+pub mod fanout;
+pub mod funds;
+pub mod history;
+pub mod keypair;
+pub mod ledger;
+pub mod metrics;
 pub mod miner;
+pub mod registry;
+pub mod scheduler;
+pub mod server;
+pub mod status;
+pub mod tls;
+
+pub use server::{serve_websocket, start_websocket_server};
 
 pub struct MasterNode {
-    rpc: RpcClient,
+    chain: Arc<dyn Chain>,
     keypair: Keypair,
-    // mapping between staking authority and best submitted proof
-    epoch_proofs: HashMap<Pubkey, Challenge>,
+    // mapping between staking authority and its proof at the start of the epoch
+    epoch_proofs: HashMap<Pubkey, Proof>,
     // channel to react over new proofs or new epoch
     rx: Receiver<SubmittedSolutionEnum>,
     state: HashMap<Pubkey, InnerState>,
+    // nodes connected to the websocket server
+    registry: WorkerRegistry,
+    metrics: Arc<Metrics>,
+    // read model shared with the HTTP API
+    status: PoolStatus,
+    clock: Arc<dyn Clock>,
+    // from the ORE config, refreshed at every epoch
+    min_difficulty: u32,
+    // share of each epoch's reward staked back into the proof
+    restake_percent: u64,
+    fee: Option<OperatorFee>,
+    // pays the transaction fees instead of the proof authority
+    fee_payer: Option<Keypair>,
+    balance_guard: Option<BalanceGuard>,
+    // simulate mine transactions before sending them
+    simulate: bool,
 }
 
 #[derive(Debug)]
@@ -33,6 +80,10 @@ struct InnerState {
     epoch_solutions: Vec<SubmittedSolution>,
     best_submitted_solution: SubmittedSolution,
     best_submitted_difficulty: u32,
+    // valid solutions sent by each miner authority during the epoch
+    contributions: HashMap<Pubkey, u64>,
+    // unix timestamp
+    started_at: u64,
 }
 
 #[derive(Debug, Clone, BorshDeserialize, BorshSerialize, PartialEq, Eq)]
@@ -41,8 +92,57 @@ pub enum SubmittedSolutionEnum {
     NewEpoch(Pubkey),
 }
 
+/// Messages sent by the master to a node over the websocket.
+#[derive(Debug, Clone, BorshDeserialize, BorshSerialize, PartialEq, Eq)]
+pub enum MasterMessage {
+    /// Sent right after the handshake so that nodes learn which proof
+    /// account they are mining for.
+    Hello { staking_authority: Pubkey },
+    /// Sent instead of the hello when admission control turns the node away.
+    Rejected { reason: String },
+}
+
+/// Messages sent by a node to the master over the websocket.
+#[derive(Debug, Clone, BorshDeserialize, BorshSerialize, PartialEq, Eq)]
+pub enum NodeMessage {
+    /// First message of every connection, checked by admission control.
+    Hello(NodeHello),
+    Solution(SubmittedSolution),
+}
+
+#[derive(Debug, Clone, BorshDeserialize, BorshSerialize, PartialEq, Eq)]
+pub struct NodeHello {
+    pub miner_authority: Pubkey,
+    pub token: Option<String>,
+}
+
 impl InnerState {
-    fn new() -> Self {
+    /// The epoch as recorded when it was not submitted.
+    fn record(
+        &self,
+        staking_authority: Pubkey,
+        challenge: Challenge,
+        ended_at: u64,
+    ) -> EpochRecord {
+        EpochRecord {
+            staking_authority,
+            challenge,
+            started_at: self.started_at,
+            ended_at,
+            solutions: self.epoch_solutions.len(),
+            contributors: self.contributions.len(),
+            best_difficulty: self.best_submitted_difficulty,
+            best_submitter: self.best_submitted_solution.miner_authority,
+            signature: None,
+            status: SubmissionStatus::Failed,
+            error: None,
+            reward: 0,
+            restaked: 0,
+            fee: 0,
+        }
+    }
+
+    fn new(started_at: u64) -> Self {
         Self {
             epoch_solutions: vec![],
             best_submitted_difficulty: 0,
@@ -51,45 +151,137 @@ impl InnerState {
                 miner_authority: Pubkey::default(),
                 solution: [0; 24],
             },
+            contributions: HashMap::new(),
+            started_at,
         }
     }
 }
 
 impl MasterNode {
     pub fn new(
-        rpc: RpcClient,
+        chain: Arc<dyn Chain>,
         keypair: Keypair,
-        proofs: HashMap<Pubkey, Challenge>,
+        proofs: HashMap<Pubkey, Proof>,
         rx: Receiver<SubmittedSolutionEnum>,
+        registry: WorkerRegistry,
+        metrics: Arc<Metrics>,
+        status: PoolStatus,
     ) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let now = clock.unix_timestamp() as u64;
         let state = proofs
             .keys()
-            .into_iter()
-            .map(|sa| (sa.clone(), InnerState::new()))
+            .map(|sa| (*sa, InnerState::new(now)))
+            .collect();
+        status.write().authorities = proofs
+            .iter()
+            .map(|(sa, proof)| {
+                let authority_status = AuthorityStatus {
+                    challenge: proof.challenge,
+                    ..Default::default()
+                };
+                (*sa, authority_status)
+            })
             .collect();
         Self {
-            rpc,
+            chain,
             keypair,
             epoch_proofs: proofs,
             rx,
             state,
+            registry,
+            metrics,
+            status,
+            clock,
+            min_difficulty: 0,
+            restake_percent: 0,
+            fee: None,
+            fee_payer: None,
+            balance_guard: None,
+            simulate: false,
+        }
+    }
+
+    /// Replaces the wall clock used to timestamp epochs.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.unix_timestamp() as u64;
+        for inner_state in self.state.values_mut() {
+            inner_state.started_at = now;
+        }
+        self.clock = clock;
+        self
+    }
+
+    /// Keeps `percent` of every epoch's reward in the proof as stake, instead
+    /// of distributing it to the miners.
+    pub fn with_restake(mut self, percent: u64) -> Self {
+        assert!(percent <= 100, "cannot restake more than 100% of rewards");
+        self.restake_percent = percent;
+        self
+    }
+
+    /// Credits `fee` to the operator out of every epoch's reward, before the
+    /// miners are paid.
+    pub fn with_fee(mut self, fee: OperatorFee) -> Self {
+        self.fee = Some(fee);
+        self
+    }
+
+    /// Has `fee_payer` pay for the transactions, so that the proof authority
+    /// only needs to sign them.
+    pub fn with_fee_payer(mut self, fee_payer: Keypair) -> Self {
+        self.fee_payer = Some(fee_payer);
+        self
+    }
+
+    /// Checks the fee payer's balance before every submission.
+    pub fn with_balance_guard(mut self, balance_guard: BalanceGuard) -> Self {
+        self.balance_guard = Some(balance_guard);
+        self
+    }
+
+    /// Simulates every mine transaction before sending it, so that one bound
+    /// to fail is reported with its reason and never sent.
+    pub fn with_simulation(mut self, simulate: bool) -> Self {
+        self.simulate = simulate;
+        self
+    }
+
+    fn payer(&self) -> &Keypair {
+        self.fee_payer.as_ref().unwrap_or(&self.keypair)
+    }
+
+    /// Nodes currently connected to the websocket server.
+    pub fn connected_workers(&self) -> Vec<WorkerInfo> {
+        self.registry.workers()
+    }
+
+    /// Reads the minimum difficulty from the ORE config, keeping the last
+    /// known one when it cannot be read.
+    fn refresh_min_difficulty(&mut self) {
+        match self.chain.get_config() {
+            Ok(config) => self.min_difficulty = config.min_difficulty as u32,
+            Err(err) => tracing::warn!(error = %err, "could not read the ORE config"),
         }
     }
 
     pub fn run(&mut self) {
+        self.refresh_min_difficulty();
+        if let Some(balance_guard) = &self.balance_guard {
+            balance_guard.check();
+        }
         loop {
-            match self.rx.recv() {
-                Ok(SubmittedSolutionEnum::SubmittedSolution(
+            match self.rx.blocking_recv() {
+                Some(SubmittedSolutionEnum::SubmittedSolution(
                     submitted_solution @ SubmittedSolution { .. },
                 )) => {
-                    log::info!("processing new solution");
                     self.process_submitted_solution(submitted_solution);
                 }
-                Ok(SubmittedSolutionEnum::NewEpoch(ref staking_authority)) => {
-                    log::info!("processing new epoch");
+                Some(SubmittedSolutionEnum::NewEpoch(ref staking_authority)) => {
                     self.process_new_epoch(staking_authority)
                 }
-                _ => panic!("wtf"),
+                // Every sender is gone: nothing left to process.
+                None => return,
             }
         }
     }
@@ -97,102 +289,361 @@ impl MasterNode {
     fn process_submitted_solution(&mut self, submitted_solution: SubmittedSolution) {
         let SubmittedSolution {
             staking_authority,
+            miner_authority,
             solution,
-            ..
         } = submitted_solution;
+        self.metrics.solutions_received.inc();
+        if self.status.is_out_of_funds() {
+            tracing::warn!(miner = %miner_authority, "out of funds, rejected solution");
+            self.metrics
+                .solutions_rejected
+                .with_label_values(&["out_of_funds"])
+                .inc();
+            return;
+        }
         if let Some(inner_state) = self.state.get_mut(&staking_authority) {
             let digest = solution[0..16].try_into().unwrap();
             let nonce = solution[16..].try_into().unwrap();
             let solution = Solution::new(digest, nonce);
-            let challenge = self.epoch_proofs.get(&staking_authority).unwrap();
-            log::info!("current challenge: {:?}", challenge);
-            if !solution.is_valid(challenge) {
-                log::error!("challenge not valid");
+            let challenge = self.epoch_proofs.get(&staking_authority).unwrap().challenge;
+            let _span = epoch_span(&staking_authority, &challenge).entered();
+            if !solution.is_valid(&challenge) {
+                tracing::warn!(miner = %miner_authority, "rejected invalid solution");
+                self.metrics
+                    .solutions_rejected
+                    .with_label_values(&["invalid"])
+                    .inc();
                 return;
             }
             let hash = solution.to_hash();
             let difficulty = hash.difficulty();
-            if dbg!(inner_state.best_submitted_difficulty) < difficulty {
-                log::info!("Better difficulty submitted: {}", difficulty);
+            if difficulty < self.min_difficulty {
+                tracing::warn!(
+                    miner = %miner_authority,
+                    difficulty,
+                    min_difficulty = self.min_difficulty,
+                    "rejected solution below the minimum difficulty"
+                );
+                self.metrics
+                    .solutions_rejected
+                    .with_label_values(&["too_easy"])
+                    .inc();
+                return;
+            }
+            self.metrics.solutions_accepted.inc();
+            tracing::debug!(
+                miner = %miner_authority,
+                difficulty,
+                best_difficulty = inner_state.best_submitted_difficulty,
+                "accepted solution"
+            );
+            if inner_state.best_submitted_difficulty < difficulty {
+                tracing::info!(miner = %miner_authority, difficulty, "better difficulty submitted");
                 inner_state.best_submitted_difficulty = difficulty;
                 inner_state.best_submitted_solution = submitted_solution.clone();
+                self.metrics
+                    .best_difficulty
+                    .with_label_values(&[&staking_authority.to_string()])
+                    .set(difficulty as i64);
             }
+            *inner_state
+                .contributions
+                .entry(miner_authority)
+                .or_default() += 1;
             inner_state.epoch_solutions.push(submitted_solution);
+
+            let mut status = self.status.write();
+            status.ledger.record_share(miner_authority);
+            if let Some(authority_status) = status.authorities.get_mut(&staking_authority) {
+                authority_status.best_difficulty = inner_state.best_submitted_difficulty;
+                authority_status.best_submitter =
+                    Some(inner_state.best_submitted_solution.miner_authority);
+                authority_status.solutions = inner_state.epoch_solutions.len();
+            }
         } else {
-            log::error!("unknown staking authority")
+            tracing::warn!(authority = %staking_authority, "solution for an unknown staking authority");
+            self.metrics
+                .solutions_rejected
+                .with_label_values(&["unknown_authority"])
+                .inc();
         }
     }
 
     fn process_new_epoch(&mut self, staking_authority: &Pubkey) {
         // 1. submit best solution (if any)
         // 2. reset proofs
-        // TODO: give rewards away
-
-        for (staking_authority, inner_state) in &mut self.state {
-            let digest = inner_state.best_submitted_solution.solution[0..16]
-                .try_into()
-                .unwrap();
-            let nonce = inner_state.best_submitted_solution.solution[16..]
-                .try_into()
-                .unwrap();
-            let solution = Solution::new(digest, nonce);
-            let proof = self.epoch_proofs.get(&staking_authority).unwrap();
-            if !solution.is_valid(&proof) {
-                log::error!("challenge not valid");
+        // 3. credit the reward to the epoch's contributors
+        if self.status.is_paused() {
+            tracing::info!(authority = %staking_authority, "submissions are paused, keeping the epoch open");
+            return;
+        }
+        if let Some(balance_guard) = &self.balance_guard {
+            if !balance_guard.check() && balance_guard.pauses() {
+                tracing::warn!(authority = %staking_authority, "out of funds, keeping the epoch open");
+                self.metrics
+                    .submissions
+                    .with_label_values(&["skipped"])
+                    .inc();
                 return;
             }
-            let mut ixs = vec![ore_api::instruction::auth(proof_pubkey(
-                self.keypair.pubkey(),
-            ))];
-            ixs.push(ore_api::instruction::mine(
-                self.keypair.pubkey(),
-                *staking_authority,
-                find_bus(),
-                solution,
-            ));
-            // todo: in parallel
-            let result = send_and_confirm(&self.rpc, &self.keypair, &ixs, false);
-            log::info!("Signature: {:?}", result);
-            inner_state.best_submitted_difficulty = 0;
-            inner_state.epoch_solutions.clear();
+        }
+        // The minimum may have gone up since the solutions were accepted.
+        self.refresh_min_difficulty();
+        let Some(inner_state) = self.state.get(staking_authority) else {
+            tracing::error!(authority = %staking_authority, "new epoch for an unknown staking authority");
+            return;
+        };
+        let best_submitted_solution = inner_state.best_submitted_solution.clone();
+        let digest = best_submitted_solution.solution[0..16].try_into().unwrap();
+        let nonce = best_submitted_solution.solution[16..].try_into().unwrap();
+        let solution = Solution::new(digest, nonce);
+        let proof = *self.epoch_proofs.get(staking_authority).unwrap();
+        let _span = epoch_span(staking_authority, &proof.challenge).entered();
+        if !solution.is_valid(&proof.challenge) {
+            if self.follow_challenge(staking_authority) {
+                return;
+            }
+            tracing::warn!("no valid solution to submit, keeping the epoch open");
+            self.metrics
+                .submissions
+                .with_label_values(&["skipped"])
+                .inc();
+            return;
+        }
+        let difficulty = inner_state.best_submitted_difficulty;
+        if difficulty < self.min_difficulty {
+            if self.follow_challenge(staking_authority) {
+                return;
+            }
+            tracing::warn!(
+                difficulty,
+                min_difficulty = self.min_difficulty,
+                "best solution is below the minimum difficulty, keeping the epoch open"
+            );
+            self.metrics
+                .submissions
+                .with_label_values(&["skipped"])
+                .inc();
+            return;
+        }
+        let mut ixs = vec![ore_api::instruction::auth(proof_pubkey(
+            self.keypair.pubkey(),
+        ))];
+        ixs.push(ore_api::instruction::mine(
+            self.keypair.pubkey(),
+            *staking_authority,
+            find_bus(),
+            solution,
+        ));
+        let timer = Instant::now();
+        let result = send_and_confirm(
+            self.chain.as_ref(),
+            &[self.payer(), &self.keypair],
+            &ixs,
+            SendOptions {
+                simulate: self.simulate,
+                ..Default::default()
+            },
+        );
+        let latency = timer.elapsed();
+        self.metrics
+            .submission_latency
+            .observe(latency.as_secs_f64());
+        let outcome = if result.is_ok() { "landed" } else { "failed" };
+        self.metrics.submissions.with_label_values(&[outcome]).inc();
+        let failure = match &result {
+            Ok(signature) => {
+                tracing::info!(
+                    %signature,
+                    difficulty = inner_state.best_submitted_difficulty,
+                    miner = %best_submitted_solution.miner_authority,
+                    latency_ms = latency.as_millis() as u64,
+                    "mine transaction landed"
+                );
+                None
+            }
+            Err(err) => {
+                let failure = FailureReason::of(err, &with_compute_budget(&ixs));
+                tracing::error!(
+                    reason = failure.label(),
+                    error = %failure,
+                    latency_ms = latency.as_millis() as u64,
+                    "mine transaction failed"
+                );
+                self.metrics
+                    .submission_failures
+                    .with_label_values(&[failure.label()])
+                    .inc();
+                Some(failure)
+            }
+        };
+        let now = self.clock.unix_timestamp() as u64;
+        if let Some(failure) = failure.as_ref().filter(|failure| keeps_epoch_open(failure)) {
+            let record = EpochRecord {
+                error: Some(failure.to_string()),
+                ..inner_state.record(*staking_authority, proof.challenge, now)
+            };
+            tracing::warn!("keeping the epoch's solutions for the next attempt");
+            self.recover(failure);
+            self.status.record_epoch(record);
+            if let FailureReason::Other(_) = failure {
+                // The transaction may have landed all the same.
+                self.follow_challenge(staking_authority);
+            }
+            return;
         }
 
         // get new proof
-        let new_proof = get_proof(&self.rpc, *staking_authority);
-        log::info!("new challenge: {:?}", new_proof.challenge);
-        self.epoch_proofs
-            .insert(*staking_authority, new_proof.challenge);
+        let new_proof = get_proof(self.chain.as_ref(), *staking_authority);
+        tracing::info!(new_challenge = %to_hex(&new_proof.challenge), "proof reset");
+        let record = self.settle_epoch(staking_authority, &proof, new_proof, now);
+        if let Some(failure) = &failure {
+            self.recover(failure);
+        }
+        self.status.record_epoch(EpochRecord {
+            signature: result.as_ref().ok().map(|sig| sig.to_string()),
+            status: match result {
+                Ok(_) => SubmissionStatus::Confirmed,
+                Err(_) => SubmissionStatus::Failed,
+            },
+            error: failure.map(|failure| failure.to_string()),
+            ..record
+        });
+    }
+
+    /// Re-reads the proof of `staking_authority` and, when its challenge is
+    /// no longer the epoch's, ends the epoch and moves on to it. Returns
+    /// whether it did. This happens when a submission given up on lands
+    /// late, or when the proof is mined from elsewhere: the master would
+    /// otherwise turn every solution for the new challenge away.
+    fn follow_challenge(&mut self, staking_authority: &Pubkey) -> bool {
+        let proof = *self.epoch_proofs.get(staking_authority).unwrap();
+        let new_proof = match self.chain.get_proof(*staking_authority) {
+            Ok(Some(new_proof)) if new_proof.challenge != proof.challenge => new_proof,
+            Ok(_) => return false,
+            Err(err) => {
+                tracing::warn!(error = %err, "could not read the proof");
+                return false;
+            }
+        };
+        tracing::warn!(
+            new_challenge = %to_hex(&new_proof.challenge),
+            "the challenge changed without a confirmed submission, moving on to it"
+        );
+        let now = self.clock.unix_timestamp() as u64;
+        let record = self.settle_epoch(staking_authority, &proof, new_proof, now);
+        // A reward means that a mine transaction landed.
+        let record = if record.reward > 0 {
+            EpochRecord {
+                status: SubmissionStatus::Confirmed,
+                ..record
+            }
+        } else {
+            EpochRecord {
+                error: Some("the challenge changed without a submission".to_string()),
+                ..record
+            }
+        };
+        self.status.record_epoch(record);
+        true
+    }
+
+    /// Ends the epoch of `staking_authority` mined for `proof`, moves on to
+    /// `new_proof` and credits the reward earned in between. Returns the
+    /// epoch's record, to be completed with the outcome of its submission.
+    fn settle_epoch(
+        &mut self,
+        staking_authority: &Pubkey,
+        proof: &Proof,
+        new_proof: Proof,
+        now: u64,
+    ) -> EpochRecord {
+        let inner_state = self.state.get_mut(staking_authority).unwrap();
+        let finished = std::mem::replace(inner_state, InnerState::new(now));
+        self.metrics
+            .best_difficulty
+            .with_label_values(&[&staking_authority.to_string()])
+            .set(0);
+        self.epoch_proofs.insert(*staking_authority, new_proof);
+        let reward = new_proof.total_rewards.saturating_sub(proof.total_rewards);
+        self.metrics.rewards_earned.inc_by(reward);
+        let restaked = self.restake(reward);
+
+        let distributable = reward - restaked;
+        let fee = self.fee.map_or(0, |fee| fee.of(distributable));
+        {
+            let mut status = self.status.write();
+            if let Some(operator) = self.fee.filter(|_| fee > 0) {
+                status.ledger.credit(operator.account, fee);
+                tracing::info!(operator = %operator.account, fee, reward, "operator fee credited");
+            }
+            let splits = status
+                .ledger
+                .distribute(distributable - fee, &finished.contributions);
+            for (miner, amount) in &splits {
+                tracing::info!(%miner, amount, reward, "reward credited");
+            }
+            status.authorities.insert(
+                *staking_authority,
+                AuthorityStatus {
+                    challenge: new_proof.challenge,
+                    ..Default::default()
+                },
+            );
+        }
+        EpochRecord {
+            reward,
+            restaked,
+            fee,
+            ..finished.record(*staking_authority, proof.challenge, now)
+        }
     }
-}
 
-pub fn start_websocket_server(host: String, solution_tx: SyncSender<SubmittedSolutionEnum>) {
-    let server = TcpListener::bind(host.as_str()).unwrap();
-    for stream in server.incoming() {
-        let solution_tx = solution_tx.clone();
-        spawn(move || {
-            let mut websocket = accept(stream.unwrap()).unwrap();
-            loop {
-                let msg = websocket.read();
-                if msg.is_err() {
-                    continue;
+    /// Reacts to a failed submission, so that the next attempt has a chance
+    /// to land.
+    fn recover(&mut self, failure: &FailureReason) {
+        match failure {
+            FailureReason::Ore(OreError::NeedsReset) => {
+                let ix = ore_api::instruction::reset(self.keypair.pubkey());
+                match send_and_confirm(
+                    self.chain.as_ref(),
+                    &[self.payer(), &self.keypair],
+                    &[ix],
+                    SendOptions::default(),
+                ) {
+                    Ok(signature) => tracing::info!(%signature, "program epoch reset"),
+                    Err(err) => tracing::error!(error = %err, "could not reset the program epoch"),
                 }
-                let msg = msg.unwrap();
-                // We do not want to send back ping/pong messages.
-                if msg.is_binary() || msg.is_text() {
-                    if let Ok(
-                        submitted_solution @ SubmittedSolutionEnum::SubmittedSolution { .. },
-                    ) = borsh::from_slice(&msg.into_data())
-                    {
-                        solution_tx.send(submitted_solution).unwrap();
-                    }
+            }
+            FailureReason::Ore(OreError::HashInvalid) => {
+                tracing::warn!("the challenge was stale, moving on to the proof's current one")
+            }
+            FailureReason::Ore(OreError::HashTooEasy) => self.refresh_min_difficulty(),
+            FailureReason::InsufficientFunds => {
+                if let Some(balance_guard) = &self.balance_guard {
+                    balance_guard.check();
                 }
             }
-        });
+            _ => {}
+        }
+    }
+
+    /// The configured share of `reward` to keep staked. Rewards are credited
+    /// to the proof balance, which is what the stake multiplier reads, so
+    /// the share only has to be held back from the miners: no transaction is
+    /// sent, and `last_stake_at` is left alone, since staking again would
+    /// switch the multiplier off for a minute.
+    fn restake(&self, reward: u64) -> u64 {
+        let amount = (reward as u128 * self.restake_percent as u128 / 100) as u64;
+        if amount > 0 {
+            tracing::info!(amount, reward, "reward share kept as stake");
+        }
+        amount
     }
 }
 
 type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
-type Challenge = [u8; 32];
+pub type Challenge = [u8; 32];
 
 #[derive(Debug, Clone, BorshDeserialize, BorshSerialize)]
 pub struct ChallengeInput {
@@ -200,6 +651,16 @@ pub struct ChallengeInput {
     remaining_time: u64,
 }
 
+impl ChallengeInput {
+    /// `remaining_time` is how long `get_hash` keeps hashing, in seconds.
+    pub fn new(challenge: Challenge, remaining_time: u64) -> Self {
+        Self {
+            challenge,
+            remaining_time,
+        }
+    }
+}
+
 #[derive(Debug, Clone, BorshDeserialize, BorshSerialize, PartialEq, Eq)]
 pub struct SubmittedSolution {
     pub staking_authority: Pubkey,
@@ -210,22 +671,72 @@ pub struct SubmittedSolution {
 pub struct NodeHashComputer {}
 
 impl NodeHashComputer {
-    pub fn connect(host: String) -> Option<Socket> {
-        let (socket, _) = tungstenite::connect(&host).expect("Can't connect");
-        log::info!("Connected to the server");
-        return Some(socket);
+    /// Connects to the master. `wss://` URLs are verified against `tls` when
+    /// given, or against the webpki roots otherwise.
+    pub fn connect(host: String, tls: Option<Arc<ClientConfig>>) -> Option<Socket> {
+        let socket = match tls {
+            None => tungstenite::connect(&host).expect("Can't connect").0,
+            Some(config) => {
+                let request = host.into_client_request().expect("Invalid master url");
+                let uri = request.uri();
+                let domain = uri.host().expect("Missing master host").to_string();
+                let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                    Some("wss") => 443,
+                    _ => 80,
+                });
+                let stream = TcpStream::connect((domain.as_str(), port)).expect("Can't connect");
+                client_tls_with_config(request, stream, None, Some(Connector::Rustls(config)))
+                    .expect("Can't connect")
+                    .0
+            }
+        };
+        tracing::info!("connected to the master");
+        Some(socket)
+    }
+
+    /// Introduces the node to the master. Must be sent before any solution.
+    pub fn send_hello(socket: &mut Socket, miner_authority: Pubkey, token: Option<String>) {
+        let hello = NodeMessage::Hello(NodeHello {
+            miner_authority,
+            token,
+        });
+        Self::send_solution(socket, borsh::to_vec(&hello).unwrap());
+    }
+
+    /// Waits for the master's hello and returns the staking authority the
+    /// pool is mining for.
+    pub fn receive_hello(socket: &mut Socket) -> Pubkey {
+        loop {
+            let msg = socket.read().expect("connection to master lost");
+            if !(msg.is_binary() || msg.is_text()) {
+                continue;
+            }
+            match borsh::from_slice(&msg.into_data()) {
+                Ok(MasterMessage::Hello { staking_authority }) => return staking_authority,
+                Ok(MasterMessage::Rejected { reason }) => {
+                    panic!("master rejected the connection: {}", reason)
+                }
+                Err(_) => {}
+            }
+        }
     }
 
-    pub fn receive_challenge(rpc_client: &RpcClient, staker_authority: Pubkey) -> ChallengeInput {
-        // ore_cli::utils::
-        let proof = get_proof(rpc_client, staker_authority);
-        let clock = get_clock(rpc_client);
-        let remaining_time = dbg!(proof
+    pub fn receive_challenge(chain: &dyn Chain, staker_authority: Pubkey) -> ChallengeInput {
+        let proof = get_proof(chain, staker_authority);
+        let clock = get_clock(chain);
+        let remaining_time = proof
             .last_hash_at
             .checked_add(ONE_MINUTE - 5)
-            .unwrap_or(dbg!(clock.unix_timestamp + 15))
-            .max(clock.unix_timestamp + 15))
-            - dbg!(clock.unix_timestamp);
+            .unwrap_or(clock.unix_timestamp + 15)
+            .max(clock.unix_timestamp + 15)
+            - clock.unix_timestamp;
+        tracing::debug!(
+            challenge = %to_hex(&proof.challenge),
+            last_hash_at = proof.last_hash_at,
+            now = clock.unix_timestamp,
+            remaining_time,
+            "received challenge"
+        );
         ChallengeInput {
             challenge: proof.challenge,
             // remaining_time: proof.last_stake_at.saturating_add(ONE_MINUTE) as _,
@@ -239,86 +750,104 @@ impl NodeHashComputer {
     }
 }
 
-pub fn get_proof(client: &RpcClient, authority: Pubkey) -> Proof {
-    let proof_address = proof_pubkey(authority);
-    let data = client
-        .get_account_data(&proof_address)
-        // .await
-        .expect("Failed to get miner account");
-    *Proof::try_from_bytes(&data).expect("Failed to parse miner account")
+pub fn get_proof(chain: &dyn Chain, authority: Pubkey) -> Proof {
+    chain
+        .get_proof(authority)
+        .expect("Failed to get miner account")
+        .expect("Miner account does not exist")
+}
+
+/// Whether the solutions of an epoch whose submission failed for `failure`
+/// can still land once the cause is dealt with: too early, before a reset,
+/// below a raised minimum that a better solution may meet, or without the
+/// SOL to pay for it. So can those of a submission whose outcome is unknown,
+/// unless it landed after all, which the proof tells. Other failures, a stale
+/// challenge first, end the epoch.
+fn keeps_epoch_open(failure: &FailureReason) -> bool {
+    matches!(
+        failure,
+        FailureReason::Ore(OreError::Spam | OreError::NeedsReset | OreError::HashTooEasy)
+            | FailureReason::InsufficientFunds
+            | FailureReason::Other(_)
+    )
+}
+
+/// Span wrapping everything the master does for one epoch of an authority.
+fn epoch_span(staking_authority: &Pubkey, challenge: &Challenge) -> Span {
+    tracing::info_span!(
+        "epoch",
+        authority = %staking_authority,
+        challenge = %to_hex(challenge)
+    )
 }
 
 pub fn proof_pubkey(authority: Pubkey) -> Pubkey {
     Pubkey::find_program_address(&[PROOF, authority.as_ref()], &ore_api::ID).0
 }
 
-pub fn get_hash(challenge: ChallengeInput) -> (Hash, u64) {
-    loop {
-        let threads = 16;
-        let handles: Vec<_> = (0..threads)
-            .map(|i| {
-                std::thread::spawn({
-                    let ChallengeInput {
-                        challenge,
-                        remaining_time,
-                    } = challenge.clone();
-                    let timer = Instant::now();
-                    let mut memory = drillx::equix::SolverMemory::new();
-                    move || {
-                        let mut nonce = rand::thread_rng().gen_range(0..u64::MAX);
-                        // let mut nonce = u64::MAX.saturating_div(threads).saturating_mul(i);
-                        let mut best_difficulty = 0;
-                        let mut best_hash = Hash::default();
-                        let mut best_nonce = 0;
-                        loop {
-                            // Create hash
-                            if let Ok(hx) = drillx::hash_with_memory(
-                                &mut memory,
-                                &challenge,
-                                &nonce.to_le_bytes(),
-                            ) {
-                                let solution = Solution::new(hx.d, nonce.to_le_bytes());
-                                if solution.is_valid(&challenge) {
-                                    let difficulty = hx.difficulty();
-                                    if difficulty.gt(&best_difficulty) {
-                                        best_difficulty = difficulty;
-                                        best_hash = hx;
-                                        best_nonce = nonce;
-                                    }
+/// Hashes `challenge` on 16 threads until `remaining_time` has passed on
+/// `clock`, and returns the hardest hash found with its nonce.
+pub fn get_hash(challenge: ChallengeInput, clock: Arc<dyn Clock>) -> (Hash, u64) {
+    let threads = 16;
+    let deadline = clock.now() + Duration::from_secs(challenge.remaining_time);
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            std::thread::spawn({
+                let challenge = challenge.challenge;
+                let clock = clock.clone();
+                let mut memory = drillx::equix::SolverMemory::new();
+                move || {
+                    let mut nonce = rand::thread_rng().gen_range(0..u64::MAX);
+                    // let mut nonce = u64::MAX.saturating_div(threads).saturating_mul(i);
+                    let mut best_difficulty = 0;
+                    let mut best_hash = Hash::default();
+                    let mut best_nonce = 0;
+                    loop {
+                        // Create hash
+                        if let Ok(hx) =
+                            drillx::hash_with_memory(&mut memory, &challenge, &nonce.to_le_bytes())
+                        {
+                            let solution = Solution::new(hx.d, nonce.to_le_bytes());
+                            if solution.is_valid(&challenge) {
+                                let difficulty = hx.difficulty();
+                                if difficulty.gt(&best_difficulty) {
+                                    best_difficulty = difficulty;
+                                    best_hash = hx;
+                                    best_nonce = nonce;
                                 }
                             }
-
-                            // Exit if time has elapsed
-                            if timer.elapsed().as_secs().ge(&remaining_time) {
-                                break;
-                            }
-                            // Increment nonce
-                            nonce = rand::thread_rng().gen_range(0..u64::MAX);
-                            // nonce += 1;
                         }
 
-                        // Return the best nonce
-                        (best_difficulty, best_hash, best_nonce)
+                        // Exit if time has elapsed
+                        if clock.now() >= deadline {
+                            break;
+                        }
+                        // Increment nonce
+                        nonce = rand::thread_rng().gen_range(0..u64::MAX);
+                        // nonce += 1;
                     }
-                })
-            })
-            .collect();
 
-        // Join handles and return best nonce
-        let mut best_difficulty = 0;
-        let mut best_nonce = 0;
-        let mut best_hash = Hash::default();
-        for h in handles {
-            if let Ok((difficulty, hash, nonce)) = h.join() {
-                if difficulty > best_difficulty {
-                    best_difficulty = difficulty;
-                    best_hash = hash;
-                    best_nonce = nonce;
+                    // Return the best nonce
+                    (best_difficulty, best_hash, best_nonce)
                 }
+            })
+        })
+        .collect();
+
+    // Join handles and return best nonce
+    let mut best_difficulty = 0;
+    let mut best_nonce = 0;
+    let mut best_hash = Hash::default();
+    for h in handles {
+        if let Ok((difficulty, hash, nonce)) = h.join() {
+            if difficulty > best_difficulty {
+                best_difficulty = difficulty;
+                best_hash = hash;
+                best_nonce = nonce;
             }
         }
-
-        log::info!("diff: {best_difficulty}");
-        return (best_hash, best_nonce);
     }
+
+    tracing::info!(difficulty = best_difficulty, "hashing done");
+    (best_hash, best_nonce)
 }
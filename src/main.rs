@@ -1,131 +1,631 @@
-use std::{
-    str::FromStr,
-    sync::mpsc::sync_channel,
-    thread::{self, sleep, spawn},
-    time::Duration,
-};
+use std::{str::FromStr, sync::Arc, thread::spawn, time::Duration};
 
 use distributed_drillx::{
-    get_hash, get_proof, miner::get_clock, start_websocket_server, MasterNode, NodeHashComputer,
-    SubmittedSolution, SubmittedSolutionEnum,
+    admission::Admission,
+    api::{start_api_server, ApiState},
+    chain::Chain,
+    clock::{ChainClock, Clock, SystemClock},
+    fanout::FanoutChain,
+    funds::BalanceGuard,
+    get_hash,
+    history::{read_jsonl, to_hex, write_csv, write_jsonl, EpochHistory, HistoryQuery},
+    keypair::load_keypair,
+    ledger::OperatorFee,
+    metrics::{start_metrics_server, Metrics, MetricsSources},
+    miner::{claim, ensure_proof, format_ore, parse_ore, stake},
+    proof_pubkey,
+    registry::WorkerRegistry,
+    scheduler::EpochScheduler,
+    serve_websocket,
+    status::PoolStatus,
+    tls::{client_config, server_config},
+    MasterNode, NodeHashComputer, NodeMessage, SubmittedSolution,
 };
 use solana_rpc_client::rpc_client::RpcClient;
 use solana_sdk::{
-    commitment_config::CommitmentConfig,
-    pubkey::Pubkey,
-    signature::Keypair,
-    signer::{EncodableKey, Signer},
+    commitment_config::CommitmentConfig, native_token::sol_to_lamports, pubkey::Pubkey,
+    signature::Keypair, signer::Signer,
 };
 use structopt::StructOpt;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tracing_subscriber::EnvFilter;
 
 fn main() {
-    env_logger::init();
-    let opt = NodeType::from_args();
-    let cluster = "https://api.devnet.solana.com";
-    let rpc_client: RpcClient =
-        RpcClient::new_with_commitment(cluster, CommitmentConfig::confirmed());
+    let Opt {
+        log_format,
+        url,
+        send_urls,
+        node_type: opt,
+    } = Opt::from_args();
+    init_logging(&log_format);
+    let rpc_client = |url: String| -> Arc<dyn Chain> {
+        Arc::new(RpcClient::new_with_commitment(
+            url,
+            CommitmentConfig::confirmed(),
+        ))
+    };
+    let chain: Arc<dyn Chain> = if send_urls.is_empty() {
+        rpc_client(url)
+    } else {
+        Arc::new(
+            send_urls
+                .into_iter()
+                .fold(FanoutChain::new(rpc_client(url)), |chain, url| {
+                    chain.with_endpoint(rpc_client(url))
+                }),
+        )
+    };
 
-    let (tx, rx) = sync_channel(1_000);
+    let (tx, rx) = mpsc::channel(1_000);
     match opt {
         NodeType::Master {
             host,
-            keypair: keypair_path,
+            keypair,
+            fee_payer,
+            tls_cert,
+            tls_key,
+            tokens,
+            allowed_miners,
+            allowed_ips,
+            metrics_host,
+            api_host,
+            api_token,
+            history_file,
+            submit_buffer,
+            restake_percent,
+            fee_percent,
+            fee_fixed,
+            fee_account,
+            min_sol,
+            pause_on_low_sol,
+            simulate,
         } => {
-            let keypair: Keypair =
-                Keypair::read_from_file(keypair_path).expect("could not read keypair");
-            let tx_cloned = tx.clone();
-            thread::spawn(move || start_websocket_server(host, tx_cloned));
+            let admission = Arc::new(Admission::new(
+                tokens,
+                allowed_miners
+                    .iter()
+                    .map(|miner| Pubkey::from_str(miner).expect("could not parse allowed miner"))
+                    .collect(),
+                allowed_ips
+                    .iter()
+                    .map(|ip| ip.parse().expect("could not parse allowed ip"))
+                    .collect(),
+            ));
+            let tls = match (tls_cert, tls_key) {
+                (Some(cert), Some(key)) => {
+                    Some(server_config(&cert, &key).expect("could not load TLS certificate"))
+                }
+                (None, None) => None,
+                _ => panic!("--tls-cert and --tls-key must be provided together"),
+            };
+            let keypair = keypair.read();
+            let fee_payer = fee_payer.read();
             // TODO: load staking authorities from a file or whatever
-            let proof = get_proof(&rpc_client, keypair.pubkey());
-            log::info!("{:?}", proof.last_hash_at);
+            let proof = ensure_proof(
+                chain.as_ref(),
+                &keypair,
+                fee_payer.as_ref().unwrap_or(&keypair),
+            )
+            .expect("could not load the proof account");
+            tracing::info!(
+                challenge = %to_hex(&proof.challenge),
+                last_hash_at = proof.last_hash_at,
+                "loaded proof"
+            );
             let staking_authority = keypair.pubkey();
+            let payer = fee_payer.as_ref().unwrap_or(&keypair).pubkey();
+            let runtime = tokio::runtime::Runtime::new().expect("could not start runtime");
+            let (shutdown_tx, shutdown_rx) = watch::channel(false);
+            let registry = WorkerRegistry::new();
+            let metrics = Arc::new(Metrics::new());
+            let status = match history_file {
+                Some(path) => PoolStatus::with_history(
+                    EpochHistory::open(path).expect("could not open epoch history"),
+                ),
+                None => PoolStatus::new(),
+            };
+            if let Some(api_host) = api_host {
+                runtime.spawn(start_api_server(
+                    api_host,
+                    ApiState {
+                        status: status.clone(),
+                        workers: registry.clone(),
+                        solution_tx: tx.clone(),
+                        admin_token: api_token,
+                    },
+                    shutdown_rx.clone(),
+                ));
+            }
+            if let Some(metrics_host) = metrics_host {
+                runtime.spawn(start_metrics_server(
+                    metrics_host,
+                    metrics.clone(),
+                    MetricsSources {
+                        workers: registry.clone(),
+                        admission: admission.clone(),
+                        solution_tx: tx.downgrade(),
+                    },
+                    shutdown_rx.clone(),
+                ));
+            }
+            // Bound before anything is spawned, so that a bad or taken --host
+            // stops the master instead of leaving it without nodes.
+            let listener = runtime
+                .block_on(TcpListener::bind(host.as_str()))
+                .expect("could not bind the websocket host");
+            let server = runtime.spawn(serve_websocket(
+                listener,
+                staking_authority,
+                tls,
+                admission,
+                registry.clone(),
+                metrics.clone(),
+                tx.clone(),
+                shutdown_rx,
+            ));
+            runtime.spawn(async move {
+                tokio::signal::ctrl_c()
+                    .await
+                    .expect("could not listen for ctrl-c");
+                tracing::info!("shutting down");
+                let _ = shutdown_tx.send(true);
+                let _ = server.await;
+                std::process::exit(0);
+            });
 
+            let clock = Arc::new(ChainClock::new(chain.clone()));
+            clock.sync();
+            let balance_guard = BalanceGuard::new(
+                chain.clone(),
+                payer,
+                sol_to_lamports(min_sol),
+                metrics.clone(),
+                status.clone(),
+            )
+            .with_pause(pause_on_low_sol);
             let mut master_node = MasterNode::new(
-                rpc_client,
+                chain.clone(),
                 keypair,
-                [(staking_authority.clone(), proof.challenge)]
-                    .into_iter()
-                    .collect(),
+                [(staking_authority, proof)].into_iter().collect(),
                 rx,
-            );
-            // spawn new epoch thread
-            spawn(move || {
-                let rpc_client: RpcClient =
-                    RpcClient::new_with_commitment(cluster, CommitmentConfig::confirmed());
-                loop {
-                    let proof = get_proof(&rpc_client, staking_authority.clone());
-                    let clock = get_clock(&rpc_client);
-
-                    let next_cutoff = dbg!(proof.last_hash_at)
-                        .saturating_add(60)
-                        .saturating_sub(1 as i64)
-                        .saturating_sub(dbg!(clock.unix_timestamp))
-                        .max(20) as u64;
-                    log::info!("Next cutoff in {next_cutoff} seconds");
-                    sleep(Duration::from_secs(next_cutoff));
-                    tx.send(distributed_drillx::SubmittedSolutionEnum::NewEpoch(
-                        staking_authority,
-                    ))
-                    .unwrap();
-                    log::info!("New epoch submitted");
-                }
-            });
+                registry,
+                metrics,
+                status,
+            )
+            .with_clock(clock.clone())
+            .with_restake(restake_percent)
+            .with_simulation(simulate)
+            .with_balance_guard(balance_guard.clone());
+            if let Some(fee_payer) = fee_payer {
+                master_node = master_node.with_fee_payer(fee_payer);
+            }
+            if fee_percent.is_some() || fee_fixed.is_some() {
+                let fee_percent = fee_percent.unwrap_or_default();
+                assert!(
+                    (0.0..=100.0).contains(&fee_percent),
+                    "--fee-percent must be between 0 and 100"
+                );
+                master_node = master_node.with_fee(OperatorFee {
+                    account: fee_account.map_or(staking_authority, |account| {
+                        Pubkey::from_str(&account).expect("could not parse fee account")
+                    }),
+                    basis_points: (fee_percent * 100.0).round() as u64,
+                    fixed: fee_fixed.map_or(0, |fixed| {
+                        parse_ore(&fixed).expect("could not parse fixed fee")
+                    }),
+                });
+            }
+            let mut scheduler = EpochScheduler::new(chain.clone(), clock.clone(), &tx)
+                .with_buffer(Duration::from_secs(submit_buffer));
+            scheduler.add_authority(staking_authority);
+            spawn(move || scheduler.run());
+            spawn(move || balance_guard.run(clock));
             master_node.run();
         }
         NodeType::Node {
             master,
             miner_authority,
-            ..
+            staking_authority,
+            ca_cert,
+            token,
         } => {
-            let staker_authority =
+            let tls =
+                ca_cert.map(|path| client_config(&path).expect("could not load CA certificate"));
+            let miner_authority =
                 Pubkey::from_str(&miner_authority).expect("could not load miner authority");
-            let mut socket = NodeHashComputer::connect(master).unwrap();
+            let mut socket = NodeHashComputer::connect(master, tls).unwrap();
+            NodeHashComputer::send_hello(&mut socket, miner_authority, token);
+            let announced_authority = NodeHashComputer::receive_hello(&mut socket);
+            let staker_authority = match staking_authority {
+                Some(staking_authority) => {
+                    let staking_authority = Pubkey::from_str(&staking_authority)
+                        .expect("could not load staking authority");
+                    if staking_authority != announced_authority {
+                        tracing::warn!(
+                            announced = %announced_authority,
+                            using = %staking_authority,
+                            "master announced another staking authority"
+                        );
+                    }
+                    staking_authority
+                }
+                None => announced_authority,
+            };
+            tracing::info!(
+                staking_authority = %staker_authority,
+                miner_authority = %miner_authority,
+                "mining"
+            );
             // move this to its own function
             loop {
-                let challenge = NodeHashComputer::receive_challenge(&rpc_client, staker_authority);
-                log::info!("challenge: {:?}", challenge);
+                let challenge =
+                    NodeHashComputer::receive_challenge(chain.as_ref(), staker_authority);
 
-                let (solution_hash, nonce) = get_hash(challenge.clone());
+                let (solution_hash, nonce) = get_hash(challenge.clone(), Arc::new(SystemClock));
                 let solution =
                     [solution_hash.d.as_slice(), nonce.to_le_bytes().as_slice()].concat();
                 // let s = Solution::new(solution_hash.d, nonce.to_le_bytes());
                 // assert!(s.is_valid(&challenge.challenge));
                 // assert!(solution.len() == 24);
-                let submitted_solution =
-                    SubmittedSolutionEnum::SubmittedSolution(SubmittedSolution {
-                        staking_authority: staker_authority,
-                        miner_authority: staker_authority,
-                        solution: solution.try_into().unwrap(),
-                    });
+                let submitted_solution = NodeMessage::Solution(SubmittedSolution {
+                    staking_authority: staker_authority,
+                    miner_authority,
+                    solution: solution.try_into().unwrap(),
+                });
                 NodeHashComputer::send_solution(
                     &mut socket,
                     borsh::to_vec(&submitted_solution).unwrap(),
                 );
             }
         }
+        NodeType::Register { keypair, fee_payer } => {
+            let keypair = keypair.read();
+            let fee_payer = fee_payer.read();
+            let proof = ensure_proof(
+                chain.as_ref(),
+                &keypair,
+                fee_payer.as_ref().unwrap_or(&keypair),
+            )
+            .expect("could not open the proof account");
+            println!("authority: {}", keypair.pubkey());
+            println!("proof: {}", proof_pubkey(keypair.pubkey()));
+            println!("challenge: {}", to_hex(&proof.challenge));
+        }
+        NodeType::Stake {
+            keypair,
+            fee_payer,
+            amount,
+        } => {
+            let keypair = keypair.read();
+            let fee_payer = fee_payer.read();
+            let amount = amount.map(|amount| parse_ore(&amount).expect("could not parse amount"));
+            let signature = stake(
+                chain.as_ref(),
+                &keypair,
+                fee_payer.as_ref().unwrap_or(&keypair),
+                amount,
+            )
+            .expect("could not stake");
+            println!("{}", signature);
+        }
+        NodeType::Claim {
+            keypair,
+            fee_payer,
+            amount,
+            beneficiary,
+        } => {
+            let keypair = keypair.read();
+            let fee_payer = fee_payer.read();
+            let amount = amount.map(|amount| parse_ore(&amount).expect("could not parse amount"));
+            let beneficiary = beneficiary
+                .map(|beneficiary| {
+                    Pubkey::from_str(&beneficiary).expect("could not parse beneficiary")
+                })
+                .unwrap_or_else(|| keypair.pubkey());
+            let signature = claim(
+                chain.as_ref(),
+                &keypair,
+                fee_payer.as_ref().unwrap_or(&keypair),
+                beneficiary,
+                amount,
+            )
+            .expect("could not claim");
+            println!("{}", signature);
+        }
+        NodeType::Balance { keypair, authority } => {
+            let authority = keypair.authority(authority);
+            let proof = chain
+                .get_proof(authority)
+                .expect("could not read the proof account");
+            let wallet = chain
+                .get_token_balance(authority)
+                .expect("could not read the token account");
+            println!(
+                "proof: {} ORE",
+                format_ore(proof.map_or(0, |proof| proof.balance))
+            );
+            println!("wallet: {} ORE", format_ore(wallet));
+        }
+        NodeType::Proof { keypair, authority } => {
+            let authority = keypair.authority(authority);
+            let proof = chain
+                .get_proof(authority)
+                .expect("could not read the proof account")
+                .unwrap_or_else(|| panic!("{} has no proof account, see `register`", authority));
+            println!("address: {}", proof_pubkey(authority));
+            println!("authority: {}", proof.authority);
+            println!("miner: {}", proof.miner);
+            println!("balance: {} ORE", format_ore(proof.balance));
+            println!("challenge: {}", to_hex(&proof.challenge));
+            println!("last hash at: {}", proof.last_hash_at);
+            println!("last stake at: {}", proof.last_stake_at);
+            println!("total hashes: {}", proof.total_hashes);
+            println!("total rewards: {} ORE", format_ore(proof.total_rewards));
+        }
+        NodeType::History {
+            file,
+            format,
+            staking_authority,
+            since,
+            until,
+        } => {
+            let records =
+                read_jsonl(std::fs::File::open(&file).expect("could not open epoch history"))
+                    .expect("could not parse epoch history");
+            let query = HistoryQuery {
+                staking_authority: staking_authority.map(|staking_authority| {
+                    Pubkey::from_str(&staking_authority).expect("could not load staking authority")
+                }),
+                since,
+                until,
+                limit: None,
+            };
+            let records = records.iter().filter(|record| query.matches(record));
+            let stdout = std::io::stdout().lock();
+            match format.as_str() {
+                "csv" => write_csv(stdout, records),
+                "jsonl" => write_jsonl(stdout, records),
+                _ => panic!("unknown format {}, expected csv or jsonl", format),
+            }
+            .expect("could not write epoch history");
+        }
     }
 }
 
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// `text`, or `json` for one JSON object per line. Filtered with `RUST_LOG`.
+    #[structopt(long = "log-format", default_value = "text", global = true)]
+    log_format: String,
+    /// RPC endpoint of the cluster to mine on.
+    #[structopt(
+        short = "u",
+        long = "url",
+        default_value = "https://api.devnet.solana.com",
+        global = true
+    )]
+    url: String,
+    /// Extra RPC endpoint transactions are sent and confirmed through, along
+    /// with `--url`. Can be repeated.
+    #[structopt(long = "send-url", global = true)]
+    send_urls: Vec<String>,
+    #[structopt(subcommand)]
+    node_type: NodeType,
+}
+
+fn init_logging(format: &str) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr);
+    match format {
+        "text" => subscriber.init(),
+        "json" => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+        _ => panic!("unknown log format {}, expected text or json", format),
+    }
+}
+
+// Parsed once at startup, the size of the master's options does not matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 enum NodeType {
     Master {
         #[structopt(short = "h", long = "host", default_value = "127.0.0.1")]
         host: String,
-        #[structopt(
-            short = "k",
-            long = "keypair",
-            default_value = "/Users/blasrodriguezgarciairizar/.config/solana/id.json"
-        )]
-        keypair: String,
+        #[structopt(flatten)]
+        keypair: KeypairOpt,
+        #[structopt(flatten)]
+        fee_payer: FeePayerOpt,
+        /// PEM certificate chain. Serves `wss://` when set along with `--tls-key`.
+        #[structopt(long = "tls-cert")]
+        tls_cert: Option<String>,
+        /// PEM private key for `--tls-cert`.
+        #[structopt(long = "tls-key")]
+        tls_key: Option<String>,
+        /// Pre-shared token accepted in a node's hello. Can be repeated; any
+        /// token is accepted when none is given.
+        #[structopt(long = "token")]
+        tokens: Vec<String>,
+        /// Miner authority allowed to connect. Can be repeated; every miner
+        /// is allowed when none is given.
+        #[structopt(long = "allow-miner")]
+        allowed_miners: Vec<String>,
+        /// IP address allowed to connect. Can be repeated; every address is
+        /// allowed when none is given.
+        #[structopt(long = "allow-ip")]
+        allowed_ips: Vec<String>,
+        /// Address serving Prometheus metrics on `/metrics`, e.g. 0.0.0.0:9090.
+        #[structopt(long = "metrics-host")]
+        metrics_host: Option<String>,
+        /// Address serving the HTTP status and admin API, e.g. 127.0.0.1:8080.
+        #[structopt(long = "api-host")]
+        api_host: Option<String>,
+        /// Bearer token required by the admin routes of the API. They are
+        /// disabled when it is not set.
+        #[structopt(long = "api-token")]
+        api_token: Option<String>,
+        /// JSON lines file the finished epochs are appended to. Epochs already
+        /// in it are loaded at startup.
+        #[structopt(long = "history-file")]
+        history_file: Option<String>,
+        /// Seconds kept on top of the measured submission latency, so that the
+        /// best solution lands before the end of the reward window.
+        #[structopt(long = "submit-buffer", default_value = "2")]
+        submit_buffer: u64,
+        /// Percentage of each epoch's reward kept in the proof as stake
+        /// rather than distributed to the miners.
+        #[structopt(long = "restake-percent", default_value = "0")]
+        restake_percent: u64,
+        /// Operator fee, as a percentage of each epoch's reward, e.g. 2.5.
+        #[structopt(long = "fee-percent")]
+        fee_percent: Option<f64>,
+        /// Operator fee in ORE per epoch, on top of `--fee-percent`.
+        #[structopt(long = "fee-fixed")]
+        fee_fixed: Option<String>,
+        /// Ledger account credited with the operator fee. The keypair's when
+        /// omitted.
+        #[structopt(long = "fee-account")]
+        fee_account: Option<String>,
+        /// SOL the fee payer should keep; the master warns below it.
+        #[structopt(long = "min-sol", default_value = "0.05")]
+        min_sol: f64,
+        /// Stop accepting solutions and submitting while the fee payer holds
+        /// less than `--min-sol`.
+        #[structopt(long = "pause-on-low-sol")]
+        pause_on_low_sol: bool,
+        /// Simulate every mine transaction before sending it, and log why it
+        /// would fail instead of sending it.
+        #[structopt(long = "simulate")]
+        simulate: bool,
     },
     Node {
         #[structopt(short = "m", long = "master", default_value = "127.0.0.1")]
         master: String,
-        #[structopt(
-            short = "m",
-            long = "miner_authority",
-            default_value = "9kQxYE42uPunfSQE4925mNZ7nV1REXtCPg944UfVcRLZ"
-        )]
+        /// Address credited for the solutions found by this node.
+        #[structopt(short = "a", long = "miner-authority")]
         miner_authority: String,
+        /// Proof authority to mine for. Learned from the master when omitted.
+        #[structopt(short = "s", long = "staking-authority")]
+        staking_authority: Option<String>,
+        /// PEM file with the only roots trusted when connecting over `wss://`.
+        #[structopt(long = "ca-cert")]
+        ca_cert: Option<String>,
+        /// Pre-shared token presented to the master.
+        #[structopt(long = "token")]
+        token: Option<String>,
+    },
+    /// Opens the proof account of a keypair, if it does not exist yet. The
+    /// master also does this at startup.
+    Register {
+        #[structopt(flatten)]
+        keypair: KeypairOpt,
+        #[structopt(flatten)]
+        fee_payer: FeePayerOpt,
+    },
+    /// Stakes ORE from the keypair's token account into its proof.
+    Stake {
+        #[structopt(flatten)]
+        keypair: KeypairOpt,
+        #[structopt(flatten)]
+        fee_payer: FeePayerOpt,
+        /// ORE to stake. The whole token balance when omitted.
+        #[structopt(long = "amount")]
+        amount: Option<String>,
     },
+    /// Claims ORE from the keypair's proof into a token account.
+    Claim {
+        #[structopt(flatten)]
+        keypair: KeypairOpt,
+        #[structopt(flatten)]
+        fee_payer: FeePayerOpt,
+        /// ORE to claim. The whole proof balance when omitted.
+        #[structopt(long = "amount")]
+        amount: Option<String>,
+        /// Wallet receiving the ORE. The keypair's when omitted.
+        #[structopt(long = "to")]
+        beneficiary: Option<String>,
+    },
+    /// Prints the ORE held by a proof and by its authority's token account.
+    Balance {
+        #[structopt(flatten)]
+        keypair: KeypairOpt,
+        /// Authority to look up instead of the keypair's.
+        #[structopt(short = "a", long = "authority")]
+        authority: Option<String>,
+    },
+    /// Prints a proof account.
+    Proof {
+        #[structopt(flatten)]
+        keypair: KeypairOpt,
+        /// Authority to look up instead of the keypair's.
+        #[structopt(short = "a", long = "authority")]
+        authority: Option<String>,
+    },
+    /// Exports an epoch history file written with `master --history-file`.
+    History {
+        #[structopt(short = "f", long = "file")]
+        file: String,
+        /// `csv` or `jsonl`.
+        #[structopt(long = "format", default_value = "csv")]
+        format: String,
+        #[structopt(short = "s", long = "staking-authority")]
+        staking_authority: Option<String>,
+        /// Only epochs that ended at or after this unix timestamp.
+        #[structopt(long = "since")]
+        since: Option<u64>,
+        /// Only epochs that ended at or before this unix timestamp.
+        #[structopt(long = "until")]
+        until: Option<u64>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct KeypairOpt {
+    /// Keypair file (`~` is expanded), `stdin` for a JSON keypair on stdin,
+    /// or `prompt:` to type a seed phrase. Defaults to the base58 secret in
+    /// `DRILLX_KEYPAIR`, then to the keypair of the Solana CLI config.
+    #[structopt(short = "k", long = "keypair")]
+    keypair: Option<String>,
+}
+
+impl KeypairOpt {
+    fn read(&self) -> Keypair {
+        read_keypair(self.keypair.as_deref())
+    }
+
+    /// `authority` when given, the keypair's public key otherwise.
+    fn authority(&self, authority: Option<String>) -> Pubkey {
+        match authority {
+            Some(authority) => Pubkey::from_str(&authority).expect("could not parse authority"),
+            None => self.read().pubkey(),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct FeePayerOpt {
+    /// Keypair paying the transaction fees and rent, in any form `--keypair`
+    /// accepts, so that the proof authority can hold little SOL. The
+    /// authority pays when omitted.
+    #[structopt(long = "fee-payer")]
+    fee_payer: Option<String>,
+}
+
+impl FeePayerOpt {
+    fn read(&self) -> Option<Keypair> {
+        self.fee_payer
+            .as_deref()
+            .map(|source| read_keypair(Some(source)))
+    }
+}
+
+fn read_keypair(source: Option<&str>) -> Keypair {
+    load_keypair(source).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    })
 }
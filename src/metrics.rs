@@ -0,0 +1,283 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::{extract::State, routing::get, Router};
+use prometheus::{
+    core::Collector, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::sync::{mpsc::WeakSender, watch};
+
+use crate::admission::Admission;
+use crate::registry::WorkerRegistry;
+use crate::SubmittedSolutionEnum;
+
+/// Prometheus metrics of the master.
+pub struct Metrics {
+    registry: Registry,
+    pub solutions_received: IntCounter,
+    pub solutions_accepted: IntCounter,
+    /// Labelled by `reason`.
+    pub solutions_rejected: IntCounterVec,
+    /// Best difficulty of the current epoch, labelled by `authority`.
+    pub best_difficulty: IntGaugeVec,
+    /// Time spent in `send_and_confirm`, in seconds.
+    pub submission_latency: Histogram,
+    /// Labelled by `outcome`.
+    pub submissions: IntCounterVec,
+    /// Failed mine transactions, labelled by `reason`.
+    pub submission_failures: IntCounterVec,
+    /// Rewards credited to the proof accounts, in grains.
+    pub rewards_earned: IntCounter,
+    /// Last known SOL balance of the fee payer, in lamports.
+    pub fee_payer_lamports: IntGauge,
+    connected_workers: IntGauge,
+    queue_depth: IntGauge,
+    admission_rejected: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let solutions_received =
+            IntCounter::new("solutions_received_total", "Solutions received from nodes").unwrap();
+        let solutions_accepted = IntCounter::new(
+            "solutions_accepted_total",
+            "Solutions that passed validation",
+        )
+        .unwrap();
+        let solutions_rejected = IntCounterVec::new(
+            Opts::new("solutions_rejected_total", "Solutions rejected, by reason"),
+            &["reason"],
+        )
+        .unwrap();
+        let best_difficulty = IntGaugeVec::new(
+            Opts::new(
+                "best_difficulty",
+                "Best difficulty submitted during the current epoch",
+            ),
+            &["authority"],
+        )
+        .unwrap();
+        let submission_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "submission_latency_seconds",
+                "Time taken to send and confirm a mine transaction",
+            )
+            .buckets(vec![0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0]),
+        )
+        .unwrap();
+        let submissions = IntCounterVec::new(
+            Opts::new("submissions_total", "Mine transactions, by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        let submission_failures = IntCounterVec::new(
+            Opts::new(
+                "submission_failures_total",
+                "Failed mine transactions, by reason",
+            ),
+            &["reason"],
+        )
+        .unwrap();
+        let rewards_earned = IntCounter::new(
+            "rewards_earned_grains_total",
+            "Rewards earned by the pool, in grains",
+        )
+        .unwrap();
+        let fee_payer_lamports = IntGauge::new(
+            "fee_payer_lamports",
+            "SOL held by the account paying transaction fees, in lamports",
+        )
+        .unwrap();
+        let connected_workers =
+            IntGauge::new("connected_workers", "Nodes currently connected").unwrap();
+        let queue_depth = IntGauge::new(
+            "solution_queue_depth",
+            "Messages waiting in the master's solution channel",
+        )
+        .unwrap();
+        let admission_rejected = IntCounter::new(
+            "admission_rejected_total",
+            "Connections turned away by admission control",
+        )
+        .unwrap();
+
+        let registry = Registry::new_custom(Some("pool".to_string()), None).unwrap();
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(solutions_received.clone()),
+            Box::new(solutions_accepted.clone()),
+            Box::new(solutions_rejected.clone()),
+            Box::new(best_difficulty.clone()),
+            Box::new(submission_latency.clone()),
+            Box::new(submissions.clone()),
+            Box::new(submission_failures.clone()),
+            Box::new(rewards_earned.clone()),
+            Box::new(fee_payer_lamports.clone()),
+            Box::new(connected_workers.clone()),
+            Box::new(queue_depth.clone()),
+            Box::new(admission_rejected.clone()),
+        ];
+        for collector in collectors {
+            registry.register(collector).unwrap();
+        }
+
+        Self {
+            registry,
+            solutions_received,
+            solutions_accepted,
+            solutions_rejected,
+            best_difficulty,
+            submission_latency,
+            submissions,
+            submission_failures,
+            rewards_earned,
+            fee_payer_lamports,
+            connected_workers,
+            queue_depth,
+            admission_rejected,
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// Where the values that are only known at scrape time come from.
+#[derive(Clone)]
+pub struct MetricsSources {
+    pub workers: WorkerRegistry,
+    pub admission: Arc<Admission>,
+    pub solution_tx: WeakSender<SubmittedSolutionEnum>,
+}
+
+impl MetricsSources {
+    fn refresh(&self, metrics: &Metrics) {
+        metrics.connected_workers.set(self.workers.len() as i64);
+        let queue_depth = self
+            .solution_tx
+            .upgrade()
+            .map(|tx| tx.max_capacity() - tx.capacity())
+            .unwrap_or_default();
+        metrics.queue_depth.set(queue_depth as i64);
+        let rejected = self.admission.rejected_count();
+        let counted = metrics.admission_rejected.get();
+        if rejected > counted {
+            metrics.admission_rejected.inc_by(rejected - counted);
+        }
+    }
+}
+
+/// Serves `GET /metrics` until `shutdown` flips to `true`.
+pub async fn start_metrics_server(
+    host: String,
+    metrics: Arc<Metrics>,
+    sources: MetricsSources,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let addr: SocketAddr = host.parse().expect("invalid metrics address");
+    axum::Server::bind(&addr)
+        .serve(router(metrics, sources).into_make_service())
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.changed().await;
+        })
+        .await
+        .unwrap();
+}
+
+fn router(metrics: Arc<Metrics>, sources: MetricsSources) -> Router {
+    Router::new()
+        .route("/metrics", get(serve_metrics))
+        .with_state((metrics, sources))
+}
+
+async fn serve_metrics(State((metrics, sources)): State<(Arc<Metrics>, MetricsSources)>) -> String {
+    sources.refresh(&metrics);
+    metrics.encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use solana_sdk::pubkey::Pubkey;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::NodeHello;
+
+    async fn scrape(metrics: &Arc<Metrics>, sources: &MetricsSources) -> String {
+        let request = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = router(metrics.clone(), sources.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn metrics_are_served_and_move() {
+        let metrics = Arc::new(Metrics::new());
+        let workers = WorkerRegistry::new();
+        workers.register("127.0.0.1:9001".parse().unwrap(), Pubkey::new_unique());
+        let admission = Arc::new(Admission::new(vec!["secret".to_string()], vec![], vec![]));
+        let hello = NodeHello {
+            miner_authority: Pubkey::new_unique(),
+            token: None,
+        };
+        assert!(admission
+            .check_hello("127.0.0.1".parse().unwrap(), &hello)
+            .is_err());
+        let (solution_tx, _solution_rx) = mpsc::channel(8);
+        solution_tx
+            .send(SubmittedSolutionEnum::NewEpoch(Pubkey::new_unique()))
+            .await
+            .unwrap();
+        let sources = MetricsSources {
+            workers,
+            admission,
+            solution_tx: solution_tx.downgrade(),
+        };
+
+        metrics
+            .solutions_rejected
+            .with_label_values(&["too_easy"])
+            .inc();
+        let body = scrape(&metrics, &sources).await;
+        for line in [
+            "pool_solutions_rejected_total{reason=\"too_easy\"} 1",
+            "pool_connected_workers 1",
+            "pool_solution_queue_depth 1",
+            "pool_admission_rejected_total 1",
+        ] {
+            assert!(body.lines().any(|l| l == line), "missing {line} in\n{body}");
+        }
+
+        // Counters keep their value between scrapes and only move forward.
+        metrics
+            .solutions_rejected
+            .with_label_values(&["too_easy"])
+            .inc();
+        let body = scrape(&metrics, &sources).await;
+        for line in [
+            "pool_solutions_rejected_total{reason=\"too_easy\"} 2",
+            "pool_admission_rejected_total 1",
+        ] {
+            assert!(body.lines().any(|l| l == line), "missing {line} in\n{body}");
+        }
+    }
+}
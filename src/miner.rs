@@ -1,65 +1,84 @@
+use std::fmt;
 use std::time::Duration;
 
-use ore_api::consts::BUS_ADDRESSES;
+use ore_api::consts::{BUS_ADDRESSES, MINT_ADDRESS, ONE_ORE, TOKEN_DECIMALS};
+use ore_api::error::OreError;
+use ore_api::state::Proof;
 use solana_program::pubkey::Pubkey;
 use solana_rpc_client::spinner;
 use solana_sdk::{
     clock::Clock,
-    commitment_config::CommitmentLevel,
     compute_budget::ComputeBudgetInstruction,
-    instruction::Instruction,
+    instruction::{Instruction, InstructionError},
     signature::{Keypair, Signature},
     signer::Signer,
-    sysvar,
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
 };
 
-use solana_client::{
-    client_error::{ClientError, ClientErrorKind, Result as ClientResult},
-    rpc_client::RpcClient,
-    rpc_config::RpcSendTransactionConfig,
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account_idempotent,
 };
-use solana_transaction_status::{TransactionConfirmationStatus, UiTransactionEncoding};
+
+use crate::chain::{custom_error, Chain};
+use crate::history::to_hex;
+use crate::proof_pubkey;
 
 const RPC_RETRIES: usize = 10;
 
+/// How [`send_and_confirm`] sends a transaction.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SendOptions {
+    /// Return as soon as the transaction is sent.
+    pub skip_confirm: bool,
+    /// Simulate the transaction first, and do not send it if it fails.
+    pub simulate: bool,
+}
+
+/// Signs `ixs` with every one of `signers`, the first of which pays the
+/// fees, and sends them until they land.
+#[allow(clippy::result_large_err)]
 pub fn send_and_confirm(
-    rpc_client: &RpcClient,
-    signer: &Keypair,
+    chain: &dyn Chain,
+    signers: &[&Keypair],
     ixs: &[Instruction],
-    skip_confirm: bool,
+    options: SendOptions,
 ) -> ClientResult<Signature> {
+    let payer = signers
+        .first()
+        .ok_or_else(|| custom_error("no signer to pay the fees"))?;
+    // The same key may be both the payer and an instruction's signer.
+    let mut unique_signers: Vec<&Keypair> = vec![];
+    for signer in signers {
+        if !unique_signers.iter().any(|s| s.pubkey() == signer.pubkey()) {
+            unique_signers.push(signer);
+        }
+    }
+
     let progress_bar = spinner::new_progress_bar();
 
-    // Set compute units
-    let mut final_ixs = vec![];
-    final_ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(1_400_000));
-    final_ixs.extend_from_slice(ixs);
+    let final_ixs = with_compute_budget(ixs);
 
     // Build tx
-    let send_cfg = RpcSendTransactionConfig {
-        skip_preflight: true,
-        preflight_commitment: Some(CommitmentLevel::Confirmed),
-        encoding: Some(UiTransactionEncoding::Base64),
-        max_retries: Some(RPC_RETRIES),
-        min_context_slot: None,
-    };
-    let mut tx = Transaction::new_with_payer(&final_ixs, Some(&signer.pubkey()));
+    let mut tx = Transaction::new_with_payer(&final_ixs, Some(&payer.pubkey()));
 
     // Sign tx
-    let (hash, _slot) = rpc_client
-        .get_latest_blockhash_with_commitment(rpc_client.commitment())
-        .unwrap();
-    tx.sign(&[&signer], hash);
+    let hash = chain.get_latest_blockhash().unwrap();
+    tx.sign(&unique_signers, hash);
+
+    if options.simulate {
+        simulate(chain, &tx, &final_ixs)?;
+    }
 
     // Submit tx
     let mut attempts = 0;
     loop {
         progress_bar.set_message(format!("Submitting transaction... (attempt {})", attempts));
-        match rpc_client.send_transaction_with_config(&tx, send_cfg) {
+        match chain.send_transaction(&tx) {
             Ok(sig) => {
                 // Skip confirmation
-                if skip_confirm {
+                if options.skip_confirm {
                     progress_bar.finish_with_message(format!("Sent: {}", sig));
                     return Ok(sig);
                 }
@@ -67,33 +86,17 @@ pub fn send_and_confirm(
                 // Confirm the tx landed
                 for _ in 0..RPC_RETRIES {
                     std::thread::sleep(Duration::from_millis(100));
-                    match rpc_client.get_signature_statuses(&[sig]) {
-                        Ok(signature_statuses) => {
-                            for status in signature_statuses.value {
-                                if let Some(status) = status {
-                                    if let Some(err) = status.err {
-                                        progress_bar.set_message(format!("Error: {}", err));
-                                        return Err(ClientError {
-                                            request: None,
-                                            kind: ClientErrorKind::Custom(err.to_string()),
-                                        });
-                                    }
-                                    if let Some(confirmation) = status.confirmation_status {
-                                        match confirmation {
-                                            TransactionConfirmationStatus::Processed => {}
-                                            TransactionConfirmationStatus::Confirmed
-                                            | TransactionConfirmationStatus::Finalized => {
-                                                return Ok(sig);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
+                    match chain.confirm_transaction(&sig) {
+                        Ok(Some(Err(err))) => {
+                            progress_bar.set_message(format!("Error: {}", err));
+                            return Err(err.into());
                         }
+                        Ok(Some(Ok(()))) => return Ok(sig),
+                        Ok(None) => {}
 
                         // Handle confirmation errors
                         Err(err) => {
-                            log::error!("{:?}", err);
+                            tracing::error!(error = ?err, "could not get signature status");
                         }
                     }
                 }
@@ -101,7 +104,7 @@ pub fn send_and_confirm(
 
             // Handle submit errors
             Err(err) => {
-                log::error!("{:?}", err);
+                tracing::error!(error = ?err, "could not send transaction");
             }
         }
 
@@ -117,14 +120,361 @@ pub fn send_and_confirm(
         }
     }
 }
+
+/// The instructions [`send_and_confirm`] sends for `ixs`: the compute
+/// budget comes first.
+pub fn with_compute_budget(ixs: &[Instruction]) -> Vec<Instruction> {
+    let mut final_ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(1_400_000)];
+    final_ixs.extend_from_slice(ixs);
+    final_ixs
+}
+
+/// Simulates `tx`, made of `ixs`, logging the compute it used, and returns
+/// the error it would fail with on chain. A simulation that cannot be run is
+/// not an error: the transaction is sent anyway.
+#[allow(clippy::result_large_err)]
+pub fn simulate(chain: &dyn Chain, tx: &Transaction, ixs: &[Instruction]) -> ClientResult<()> {
+    let simulation = match chain.simulate_transaction(tx) {
+        Ok(simulation) => simulation,
+        Err(err) => {
+            tracing::warn!(error = %err, "could not simulate transaction");
+            return Ok(());
+        }
+    };
+    match simulation.result {
+        Ok(()) => {
+            tracing::debug!(
+                units_consumed = simulation.units_consumed,
+                "simulation succeeded"
+            );
+            Ok(())
+        }
+        Err(err) => {
+            let err = ClientError::from(err);
+            tracing::error!(
+                error = %FailureReason::of(&err, ixs),
+                units_consumed = simulation.units_consumed,
+                logs = ?simulation.logs,
+                "simulation failed"
+            );
+            Err(err)
+        }
+    }
+}
+
+/// Why a transaction sent by [`send_and_confirm`] failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureReason {
+    /// Rejected by the ORE program.
+    Ore(OreError),
+    /// The fee payer cannot pay for the transaction.
+    InsufficientFunds,
+    /// Failed on chain for another reason.
+    Transaction(TransactionError),
+    /// Could not be sent or confirmed, or failed with the custom error of a
+    /// program other than ORE.
+    Other(String),
+}
+
+impl FailureReason {
+    /// Classifies the failure of a transaction made of `ixs`.
+    pub fn of(err: &ClientError, ixs: &[Instruction]) -> Self {
+        match err.get_transaction_error() {
+            Some(
+                TransactionError::InsufficientFundsForFee
+                | TransactionError::InsufficientFundsForRent { .. },
+            ) => Self::InsufficientFunds,
+            Some(tx_err) => match ore_error(&tx_err, ixs) {
+                Some(ore_err) => Self::Ore(ore_err),
+                // Another program's custom error, which the codes don't name.
+                None if matches!(
+                    tx_err,
+                    TransactionError::InstructionError(_, InstructionError::Custom(_))
+                ) =>
+                {
+                    Self::Other(err.to_string())
+                }
+                None => Self::Transaction(tx_err),
+            },
+            None => Self::Other(err.to_string()),
+        }
+    }
+
+    /// Short name of the reason, used as a metrics label.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Ore(OreError::NeedsReset) => "needs_reset",
+            Self::Ore(OreError::HashInvalid) => "hash_invalid",
+            Self::Ore(OreError::HashTooEasy) => "hash_too_easy",
+            Self::Ore(OreError::ClaimTooLarge) => "claim_too_large",
+            Self::Ore(OreError::ClockInvalid) => "clock_invalid",
+            Self::Ore(OreError::Spam) => "spam",
+            Self::Ore(OreError::MaxSupply) => "max_supply",
+            Self::Ore(OreError::AuthFailed) => "auth_failed",
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::Transaction(_) => "transaction",
+            Self::Other(_) => "other",
+        }
+    }
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ore(err) => write!(f, "{}", err),
+            Self::InsufficientFunds => write!(f, "the fee payer cannot pay for the transaction"),
+            Self::Transaction(err) => write!(f, "{}", err),
+            Self::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// The ORE program error `err` carries, if any. `ixs` are the instructions
+/// of the failed transaction: custom errors of other programs, like the
+/// token program, share the same codes and are left alone.
+pub fn ore_error(err: &TransactionError, ixs: &[Instruction]) -> Option<OreError> {
+    let TransactionError::InstructionError(index, InstructionError::Custom(code)) = err else {
+        return None;
+    };
+    if ixs.get(*index as usize)?.program_id != ore_api::ID {
+        return None;
+    }
+    Some(match code {
+        0 => OreError::NeedsReset,
+        1 => OreError::HashInvalid,
+        2 => OreError::HashTooEasy,
+        3 => OreError::ClaimTooLarge,
+        4 => OreError::ClockInvalid,
+        5 => OreError::Spam,
+        6 => OreError::MaxSupply,
+        7 => OreError::AuthFailed,
+        _ => return None,
+    })
+}
+
+/// Returns the proof of `signer`, opening it first when the account does not
+/// exist yet. The signer is set as the proof's miner; `payer` pays the rent
+/// and the fees, and may be the signer itself.
+#[allow(clippy::result_large_err)]
+pub fn ensure_proof(chain: &dyn Chain, signer: &Keypair, payer: &Keypair) -> ClientResult<Proof> {
+    let authority = signer.pubkey();
+    if let Some(proof) = chain.get_proof(authority)? {
+        return Ok(proof);
+    }
+    let address = proof_pubkey(authority);
+    tracing::info!(%authority, proof = %address, "opening proof account");
+    let ix = ore_api::instruction::open(authority, authority, payer.pubkey());
+    send_and_confirm(chain, &[payer, signer], &[ix], SendOptions::default())?;
+    let proof = chain
+        .get_proof(authority)?
+        .ok_or_else(|| custom_error("proof account was not created"))?;
+    tracing::info!(
+        %authority,
+        proof = %address,
+        challenge = %to_hex(&proof.challenge),
+        "proof account opened"
+    );
+    Ok(proof)
+}
+
+/// Stakes `amount` ORE from the signer's token account into its proof, or
+/// the whole token balance when `None`. `payer` pays the fees.
+#[allow(clippy::result_large_err)]
+pub fn stake(
+    chain: &dyn Chain,
+    signer: &Keypair,
+    payer: &Keypair,
+    amount: Option<u64>,
+) -> ClientResult<Signature> {
+    let authority = signer.pubkey();
+    let amount = match amount {
+        Some(amount) => amount,
+        None => chain.get_token_balance(authority)?,
+    };
+    if amount == 0 {
+        return Err(custom_error("nothing to stake"));
+    }
+    let sender = get_associated_token_address(&authority, &MINT_ADDRESS);
+    let ix = ore_api::instruction::stake(authority, sender, amount);
+    let signature = send_and_confirm(chain, &[payer, signer], &[ix], SendOptions::default())?;
+    tracing::info!(%authority, amount = %format_ore(amount), %signature, "staked");
+    Ok(signature)
+}
+
+/// Claims `amount` ORE from the signer's proof into the token account of
+/// `beneficiary`, creating it if needed, or the whole proof balance when
+/// `None`. `payer` pays the fees and the rent of the token account.
+#[allow(clippy::result_large_err)]
+pub fn claim(
+    chain: &dyn Chain,
+    signer: &Keypair,
+    payer: &Keypair,
+    beneficiary: Pubkey,
+    amount: Option<u64>,
+) -> ClientResult<Signature> {
+    let authority = signer.pubkey();
+    let proof = chain
+        .get_proof(authority)?
+        .ok_or_else(|| custom_error("proof account does not exist"))?;
+    let amount = amount.unwrap_or(proof.balance);
+    if amount == 0 {
+        return Err(custom_error("nothing to claim"));
+    }
+    if amount > proof.balance {
+        return Err(custom_error(format!(
+            "cannot claim {} ORE, the proof holds {}",
+            format_ore(amount),
+            format_ore(proof.balance)
+        )));
+    }
+    let beneficiary_tokens = get_associated_token_address(&beneficiary, &MINT_ADDRESS);
+    let ixs = [
+        create_associated_token_account_idempotent(
+            &payer.pubkey(),
+            &beneficiary,
+            &MINT_ADDRESS,
+            &spl_token::id(),
+        ),
+        ore_api::instruction::claim(authority, beneficiary_tokens, amount),
+    ];
+    let signature = send_and_confirm(chain, &[payer, signer], &ixs, SendOptions::default())?;
+    tracing::info!(%authority, %beneficiary, amount = %format_ore(amount), %signature, "claimed");
+    Ok(signature)
+}
+
+/// Formats an amount of the smallest ORE unit as ORE.
+pub fn format_ore(amount: u64) -> String {
+    format!(
+        "{}.{:0width$}",
+        amount / ONE_ORE,
+        amount % ONE_ORE,
+        width = TOKEN_DECIMALS as usize
+    )
+}
+
+/// Parses an amount of ORE, with up to `TOKEN_DECIMALS` decimals, into the
+/// smallest ORE unit.
+pub fn parse_ore(amount: &str) -> Option<u64> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if !digits(whole)
+        || !digits(fraction)
+        || fraction.len() > TOKEN_DECIMALS as usize
+        || (whole.is_empty() && fraction.is_empty())
+    {
+        return None;
+    }
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let fraction: u64 = format!("{:0<width$}", fraction, width = TOKEN_DECIMALS as usize)
+        .parse()
+        .ok()?;
+    whole.checked_mul(ONE_ORE)?.checked_add(fraction)
+}
+
 // TODO Pick a better strategy (avoid draining bus)
 pub fn find_bus() -> Pubkey {
     BUS_ADDRESSES[2]
 }
 
-pub fn get_clock(client: &RpcClient) -> Clock {
-    let data = client
-        .get_account_data(&sysvar::clock::ID)
-        .expect("Failed to get miner account");
-    bincode::deserialize::<Clock>(&data).expect("Failed to deserialize clock")
+pub fn get_clock(chain: &dyn Chain) -> Clock {
+    chain.get_clock().expect("Failed to get clock")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_chain::FakeChain;
+
+    const START: i64 = 1_700_000_000;
+
+    #[test]
+    fn ore_amounts_parse_and_format() {
+        assert_eq!(parse_ore("2.5"), Some(ONE_ORE * 5 / 2));
+        assert_eq!(parse_ore("3"), Some(3 * ONE_ORE));
+        assert_eq!(parse_ore(".00000000001"), Some(1));
+        assert_eq!(parse_ore("0.000000000001"), None);
+        for invalid in ["", ".", "-1", "1.2.3", "1e3", "18446744073709551615"] {
+            assert_eq!(parse_ore(invalid), None, "{}", invalid);
+        }
+        assert_eq!(format_ore(ONE_ORE * 5 / 2), "2.50000000000");
+        assert_eq!(format_ore(1), "0.00000000001");
+    }
+
+    #[test]
+    fn only_errors_of_ore_instructions_are_decoded() {
+        let authority = Pubkey::new_unique();
+        let tokens = get_associated_token_address(&authority, &MINT_ADDRESS);
+        let ixs = with_compute_budget(&[
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &tokens,
+                &tokens,
+                &authority,
+                &[],
+                1,
+            )
+            .unwrap(),
+            ore_api::instruction::claim(authority, tokens, 1),
+        ]);
+        let failed = |index: u8, code: u32| {
+            TransactionError::InstructionError(index, InstructionError::Custom(code))
+        };
+        // TokenError::InsufficientFunds has the code of OreError::HashInvalid.
+        let err = ClientError::from(failed(1, 1));
+        assert_eq!(ore_error(&failed(1, 1), &ixs), None);
+        assert_eq!(
+            FailureReason::of(&err, &ixs),
+            FailureReason::Other(err.to_string())
+        );
+        assert_eq!(
+            ore_error(&failed(2, 3), &ixs),
+            Some(OreError::ClaimTooLarge)
+        );
+        assert_eq!(ore_error(&failed(2, 42), &ixs), None);
+        assert_eq!(ore_error(&failed(3, 3), &ixs), None);
+        assert_eq!(
+            FailureReason::of(&ClientError::from(failed(2, 5)), &ixs).label(),
+            "spam"
+        );
+    }
+
+    #[test]
+    fn operators_stake_and_claim() {
+        let chain = FakeChain::new(START);
+        let keypair = Keypair::new();
+        let authority = keypair.pubkey();
+        let ore = |amount: &str| parse_ore(amount).unwrap();
+        chain.open_proof(authority, [7; 32]);
+        chain.set_token_balance(authority, ore("10"));
+
+        stake(&chain, &keypair, &keypair, Some(ore("2.5"))).unwrap();
+        assert_eq!(chain.proof(authority).unwrap().balance, ore("2.5"));
+        assert_eq!(chain.token_balance(authority), ore("7.5"));
+        assert_eq!(chain.proof(authority).unwrap().last_stake_at, START);
+
+        // Without an amount, everything moves.
+        stake(&chain, &keypair, &keypair, None).unwrap();
+        assert_eq!(chain.proof(authority).unwrap().balance, ore("10"));
+        assert_eq!(chain.token_balance(authority), 0);
+        assert!(stake(&chain, &keypair, &keypair, None).is_err());
+
+        let beneficiary = Pubkey::new_unique();
+        claim(
+            &chain,
+            &keypair,
+            &keypair,
+            beneficiary,
+            Some(ore("0.00000000001")),
+        )
+        .unwrap();
+        assert_eq!(chain.token_balance(beneficiary), 1);
+        assert!(claim(&chain, &keypair, &keypair, beneficiary, Some(ore("11"))).is_err());
+        claim(&chain, &keypair, &keypair, authority, None).unwrap();
+        assert_eq!(chain.proof(authority).unwrap().balance, 0);
+        assert_eq!(format_ore(chain.token_balance(authority)), "9.99999999999");
+    }
 }
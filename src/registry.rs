@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::Notify;
+
+/// A node currently connected to the master.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerInfo {
+    pub id: u64,
+    pub address: SocketAddr,
+    pub miner_authority: Pubkey,
+    pub connected_since: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// Live connections of the websocket server, shared with the master.
+#[derive(Debug, Clone, Default)]
+pub struct WorkerRegistry {
+    inner: Arc<Mutex<RegistryInner>>,
+}
+
+#[derive(Debug, Default)]
+struct RegistryInner {
+    next_id: u64,
+    workers: HashMap<u64, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    info: WorkerInfo,
+    kick: Arc<Notify>,
+}
+
+impl WorkerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a freshly admitted worker. Returns its id and the handle notified
+    /// when the worker gets kicked.
+    pub fn register(&self, address: SocketAddr, miner_authority: Pubkey) -> (u64, Arc<Notify>) {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let now = SystemTime::now();
+        let kick = Arc::new(Notify::new());
+        inner.workers.insert(
+            id,
+            Entry {
+                info: WorkerInfo {
+                    id,
+                    address,
+                    miner_authority,
+                    connected_since: now,
+                    last_seen: now,
+                },
+                kick: kick.clone(),
+            },
+        );
+        (id, kick)
+    }
+
+    /// Asks the server to disconnect the worker. Returns `false` when no such
+    /// worker is connected.
+    pub fn kick(&self, id: u64) -> bool {
+        match self.inner.lock().unwrap().workers.get(&id) {
+            Some(entry) => {
+                entry.kick.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records that the worker just sent something.
+    pub fn touch(&self, id: u64) {
+        if let Some(entry) = self.inner.lock().unwrap().workers.get_mut(&id) {
+            entry.info.last_seen = SystemTime::now();
+        }
+    }
+
+    pub fn remove(&self, id: u64) -> Option<WorkerInfo> {
+        self.inner
+            .lock()
+            .unwrap()
+            .workers
+            .remove(&id)
+            .map(|entry| entry.info)
+    }
+
+    pub fn get(&self, id: u64) -> Option<WorkerInfo> {
+        self.inner
+            .lock()
+            .unwrap()
+            .workers
+            .get(&id)
+            .map(|entry| entry.info.clone())
+    }
+
+    /// Connected workers, oldest connection first.
+    pub fn workers(&self) -> Vec<WorkerInfo> {
+        let mut workers: Vec<_> = self
+            .inner
+            .lock()
+            .unwrap()
+            .workers
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect();
+        workers.sort_by_key(|worker| worker.id);
+        workers
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ore_api::consts::{EPOCH_DURATION, ONE_MINUTE, TOLERANCE};
+use ore_api::state::{Config, Proof};
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::chain::Chain;
+use crate::clock::Clock;
+use crate::history::to_hex;
+use crate::{Challenge, SubmittedSolutionEnum};
+
+/// How often the proof is read while waiting for a submission to land, or
+/// after it could not be read.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the proof may keep its challenge after a cutoff before the
+/// master is asked to submit again, e.g. when nothing valid was found yet
+/// or the transaction failed.
+const RETRY_AFTER: Duration = Duration::from_secs(20);
+
+/// Default margin kept on top of the expected submission latency.
+pub const DEFAULT_BUFFER: Duration = Duration::from_secs(2);
+
+/// Submission latency assumed until one has been measured.
+const INITIAL_LATENCY: Duration = Duration::from_secs(4);
+
+const EVENTS_CAPACITY: usize = 64;
+
+/// What the scheduler observed, for whoever embeds the pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EpochEvent {
+    /// The proof has a new challenge to mine until `cutoff`, the unix
+    /// timestamp at which the best solution will be submitted.
+    Started {
+        staking_authority: Pubkey,
+        challenge: Challenge,
+        cutoff: i64,
+    },
+    /// The cutoff passed and the master was asked to submit.
+    CutoffReached {
+        staking_authority: Pubkey,
+        challenge: Challenge,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Phase {
+    /// The proof has not been read yet, or could not be read.
+    Unknown { poll_at: Duration },
+    /// Mining the current challenge until the cutoff.
+    Mining { cutoff: Duration },
+    /// The master was asked to submit; waiting for the proof to move.
+    Submitted { since: Duration, poll_at: Duration },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracked {
+    challenge: Option<Challenge>,
+    phase: Phase,
+}
+
+/// Tells the master when to close each authority's epoch, following the
+/// proofs on chain: once the cutoff of a challenge passes it requests a new
+/// epoch, then watches the proof until the submission lands and a new
+/// challenge shows up.
+pub struct EpochScheduler {
+    chain: Arc<dyn Chain>,
+    clock: Arc<dyn Clock>,
+    // weak so that the scheduler does not keep the master running
+    master: mpsc::WeakSender<SubmittedSolutionEnum>,
+    events: broadcast::Sender<EpochEvent>,
+    authorities: HashMap<Pubkey, Tracked>,
+    buffer: Duration,
+    // moving average of the time from a cutoff to the submission landing
+    latency: Duration,
+}
+
+impl EpochScheduler {
+    pub fn new(
+        chain: Arc<dyn Chain>,
+        clock: Arc<dyn Clock>,
+        master: &mpsc::Sender<SubmittedSolutionEnum>,
+    ) -> Self {
+        Self {
+            chain,
+            clock,
+            master: master.downgrade(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            authorities: HashMap::new(),
+            buffer: DEFAULT_BUFFER,
+            latency: INITIAL_LATENCY,
+        }
+    }
+
+    /// Margin kept on top of the measured submission latency, against a
+    /// slow transaction landing past the end of the window.
+    pub fn with_buffer(mut self, buffer: Duration) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    pub fn add_authority(&mut self, staking_authority: Pubkey) {
+        let poll_at = self.clock.now();
+        self.authorities.insert(
+            staking_authority,
+            Tracked {
+                challenge: None,
+                phase: Phase::Unknown { poll_at },
+            },
+        );
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EpochEvent> {
+        self.events.subscribe()
+    }
+
+    /// Schedules epochs until the master goes away.
+    pub fn run(&mut self) {
+        while let Some(wake_at) = self.step() {
+            self.clock.sleep_until(wake_at);
+        }
+    }
+
+    /// Handles whatever is due and returns when to look again, or `None`
+    /// once the master is gone.
+    pub fn step(&mut self) -> Option<Duration> {
+        let now = self.clock.now();
+        let authorities: Vec<Pubkey> = self.authorities.keys().copied().collect();
+        let mut wake_at = None;
+        for staking_authority in authorities {
+            let next = self.advance(staking_authority, now)?;
+            wake_at = Some(wake_at.map_or(next, |wake_at: Duration| wake_at.min(next)));
+        }
+        Some(wake_at.unwrap_or(now + POLL_INTERVAL))
+    }
+
+    fn advance(&mut self, staking_authority: Pubkey, now: Duration) -> Option<Duration> {
+        let tracked = self.authorities[&staking_authority];
+        match tracked.phase {
+            Phase::Mining { cutoff } if cutoff > now => Some(cutoff),
+            Phase::Unknown { poll_at } | Phase::Submitted { poll_at, .. } if poll_at > now => {
+                Some(poll_at)
+            }
+            Phase::Mining { .. } => {
+                let challenge = tracked.challenge.unwrap_or_default();
+                tracing::info!(
+                    authority = %staking_authority,
+                    challenge = %to_hex(&challenge),
+                    "cutoff reached"
+                );
+                self.master
+                    .upgrade()?
+                    .blocking_send(SubmittedSolutionEnum::NewEpoch(staking_authority))
+                    .ok()?;
+                let _ = self.events.send(EpochEvent::CutoffReached {
+                    staking_authority,
+                    challenge,
+                });
+                let poll_at = now + POLL_INTERVAL;
+                self.set_phase(
+                    staking_authority,
+                    Phase::Submitted {
+                        since: now,
+                        poll_at,
+                    },
+                );
+                Some(poll_at)
+            }
+            Phase::Unknown { .. } | Phase::Submitted { .. } => {
+                Some(self.refresh(staking_authority, tracked, now))
+            }
+        }
+    }
+
+    /// Reads the proof and moves on to the next epoch if its challenge
+    /// changed.
+    fn refresh(&mut self, staking_authority: Pubkey, tracked: Tracked, now: Duration) -> Duration {
+        let poll_at = now + POLL_INTERVAL;
+        let proof = match self.chain.get_proof(staking_authority) {
+            Ok(Some(proof)) => proof,
+            Ok(None) => {
+                tracing::warn!(authority = %staking_authority, "proof account does not exist");
+                self.retry_refresh(staking_authority, tracked, poll_at);
+                return poll_at;
+            }
+            Err(err) => {
+                tracing::warn!(authority = %staking_authority, error = %err, "could not read proof");
+                self.retry_refresh(staking_authority, tracked, poll_at);
+                return poll_at;
+            }
+        };
+        self.clock.sync();
+        // Only needed for the reset deadline, which can be left out.
+        let config = self
+            .chain
+            .get_config()
+            .map_err(|err| tracing::warn!(error = %err, "could not read config"))
+            .ok();
+
+        let phase = match tracked.phase {
+            Phase::Submitted { since, .. } if tracked.challenge == Some(proof.challenge) => {
+                if now < since + RETRY_AFTER {
+                    Phase::Submitted { since, poll_at }
+                } else {
+                    tracing::warn!(
+                        authority = %staking_authority,
+                        "challenge did not change since the cutoff, asking again"
+                    );
+                    Phase::Mining { cutoff: now }
+                }
+            }
+            _ => {
+                if let Phase::Submitted { since, .. } = tracked.phase {
+                    self.measure_latency(since, &proof);
+                }
+                let submit_at =
+                    submission_time(&proof, config.as_ref(), self.buffer + self.latency);
+                let cutoff = submit_at.as_secs() as i64;
+                if tracked.challenge != Some(proof.challenge) {
+                    tracing::info!(
+                        authority = %staking_authority,
+                        challenge = %to_hex(&proof.challenge),
+                        cutoff,
+                        "epoch started"
+                    );
+                    let _ = self.events.send(EpochEvent::Started {
+                        staking_authority,
+                        challenge: proof.challenge,
+                        cutoff,
+                    });
+                }
+                Phase::Mining { cutoff: submit_at }
+            }
+        };
+        self.authorities.insert(
+            staking_authority,
+            Tracked {
+                challenge: Some(proof.challenge),
+                phase,
+            },
+        );
+        match phase {
+            Phase::Mining { cutoff } => cutoff,
+            _ => poll_at,
+        }
+    }
+
+    /// Updates the latency estimate with a submission requested at `since`
+    /// that landed at the proof's `last_hash_at`.
+    fn measure_latency(&mut self, since: Duration, proof: &Proof) {
+        let landed = Duration::from_secs(proof.last_hash_at.max(0) as u64);
+        let sample = landed.saturating_sub(since);
+        self.latency = (self.latency + sample) / 2;
+        tracing::debug!(
+            sample_ms = sample.as_millis() as u64,
+            latency_ms = self.latency.as_millis() as u64,
+            "submission latency measured"
+        );
+    }
+
+    fn retry_refresh(&mut self, staking_authority: Pubkey, tracked: Tracked, poll_at: Duration) {
+        let phase = match tracked.phase {
+            Phase::Submitted { since, .. } => Phase::Submitted { since, poll_at },
+            _ => Phase::Unknown { poll_at },
+        };
+        self.set_phase(staking_authority, phase);
+    }
+
+    fn set_phase(&mut self, staking_authority: Pubkey, phase: Phase) {
+        if let Some(tracked) = self.authorities.get_mut(&staking_authority) {
+            tracked.phase = phase;
+        }
+    }
+}
+
+/// When the master should submit the best solution for `proof`, so that it
+/// lands `lead` before the window closes.
+///
+/// The program rejects a hash before `last_hash_at + ONE_MINUTE - TOLERANCE`
+/// as spam, and cuts the reward for every second past
+/// `last_hash_at + ONE_MINUTE + TOLERANCE`; in between, the later the
+/// submission the longer the nodes get to find a harder hash. If the
+/// program epoch in `config` ends inside that window, mining after it fails
+/// until someone resets, so the window closes there instead.
+pub fn submission_time(proof: &Proof, config: Option<&Config>, lead: Duration) -> Duration {
+    let target = proof.last_hash_at + ONE_MINUTE;
+    let opens = target - TOLERANCE;
+    let mut closes = target + TOLERANCE;
+    if let Some(config) = config {
+        let reset_at = config.last_reset_at + EPOCH_DURATION;
+        if reset_at > opens {
+            closes = closes.min(reset_at - 1);
+        }
+    }
+    let closes = Duration::from_secs(closes.max(0) as u64);
+    let opens = Duration::from_secs(opens.max(0) as u64);
+    closes.saturating_sub(lead).max(opens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_chain::FakeChain;
+
+    const START: i64 = 1_700_000_000;
+
+    fn at(seconds: i64) -> Duration {
+        Duration::from_secs(seconds as u64)
+    }
+
+    #[test]
+    fn submissions_are_timed_inside_the_reward_window() {
+        let chain = FakeChain::new(START);
+        let proof = chain.open_proof(Pubkey::new_unique(), [7; 32]);
+        let lead = Duration::from_secs(3);
+
+        assert_eq!(
+            submission_time(&proof, None, lead),
+            at(START + ONE_MINUTE + TOLERANCE - 3)
+        );
+        // Never before the spam window opens.
+        assert_eq!(
+            submission_time(&proof, None, Duration::from_secs(60)),
+            at(START + ONE_MINUTE - TOLERANCE)
+        );
+    }
+
+    #[test]
+    fn a_program_epoch_ending_inside_the_window_closes_it() {
+        let chain = FakeChain::new(START);
+        let proof = chain.open_proof(Pubkey::new_unique(), [7; 32]);
+        let lead = Duration::from_secs(3);
+
+        let mut config = chain.get_config().unwrap();
+        config.last_reset_at = START + 2;
+        assert_eq!(
+            submission_time(&proof, Some(&config), lead),
+            at(START + 2 + EPOCH_DURATION - 1 - 3)
+        );
+        // One ending before it does not matter: someone resets in between.
+        config.last_reset_at = START - 10;
+        assert_eq!(
+            submission_time(&proof, Some(&config), lead),
+            at(START + ONE_MINUTE + TOLERANCE - 3)
+        );
+    }
+}
@@ -0,0 +1,451 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rustls::ServerConfig;
+use solana_sdk::pubkey::Pubkey;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{accept_async, WebSocketStream};
+use tracing::{field, Instrument, Span};
+use tungstenite::Message;
+
+use crate::admission::Admission;
+use crate::metrics::Metrics;
+use crate::registry::WorkerRegistry;
+use crate::{MasterMessage, NodeHello, NodeMessage, SubmittedSolutionEnum};
+
+/// Messages waiting to be written to a single node. A node that lets this
+/// fill up is too slow to keep and gets disconnected.
+const OUTBOUND_QUEUE: usize = 32;
+
+/// Time a node has to send its hello after the websocket handshake.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Nodes never read the socket after the hello, so they do not answer pings:
+/// the pings only make writes to a dead connection fail. A node counts as
+/// alive as long as it sends solutions, about one per epoch, and is dropped
+/// after a few epochs without any.
+const HEARTBEAT: Heartbeat = Heartbeat {
+    interval: Duration::from_secs(30),
+    timeout: Duration::from_secs(180),
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Heartbeat {
+    /// How often the master pings its nodes.
+    interval: Duration,
+    /// How long a node may go without sending anything.
+    timeout: Duration,
+}
+
+struct ServerContext {
+    staking_authority: Pubkey,
+    heartbeat: Heartbeat,
+    admission: Arc<Admission>,
+    registry: WorkerRegistry,
+    metrics: Arc<Metrics>,
+    solution_tx: mpsc::Sender<SubmittedSolutionEnum>,
+}
+
+/// Binds `host` and serves nodes on it, see [`serve_websocket`]. Fails only
+/// when `host` cannot be bound.
+#[allow(clippy::too_many_arguments)]
+pub async fn start_websocket_server(
+    host: String,
+    staking_authority: Pubkey,
+    tls: Option<Arc<ServerConfig>>,
+    admission: Arc<Admission>,
+    registry: WorkerRegistry,
+    metrics: Arc<Metrics>,
+    solution_tx: mpsc::Sender<SubmittedSolutionEnum>,
+    shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let server = TcpListener::bind(host.as_str()).await?;
+    serve_websocket(
+        server,
+        staking_authority,
+        tls,
+        admission,
+        registry,
+        metrics,
+        solution_tx,
+        shutdown,
+    )
+    .await;
+    Ok(())
+}
+
+/// Accepts nodes on an already bound listener until `shutdown` flips to
+/// `true`, then closes every connection and waits for them to finish.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_websocket(
+    server: TcpListener,
+    staking_authority: Pubkey,
+    tls: Option<Arc<ServerConfig>>,
+    admission: Arc<Admission>,
+    registry: WorkerRegistry,
+    metrics: Arc<Metrics>,
+    solution_tx: mpsc::Sender<SubmittedSolutionEnum>,
+    shutdown: watch::Receiver<bool>,
+) {
+    let context = ServerContext {
+        staking_authority,
+        heartbeat: HEARTBEAT,
+        admission,
+        registry,
+        metrics,
+        solution_tx,
+    };
+    accept_nodes(server, tls, context, shutdown).await
+}
+
+async fn accept_nodes(
+    server: TcpListener,
+    tls: Option<Arc<ServerConfig>>,
+    context: ServerContext,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let acceptor = tls.map(TlsAcceptor::from);
+    let context = Arc::new(context);
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = server.accept() => {
+                let (stream, addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::error!(error = ?err, "could not accept connection");
+                        continue;
+                    }
+                };
+                if context.admission.check_ip(addr.ip()).is_err() {
+                    continue;
+                }
+                // worker_id and miner_authority are filled in once the node
+                // is admitted.
+                let span = tracing::info_span!(
+                    "connection",
+                    %addr,
+                    worker_id = field::Empty,
+                    miner_authority = field::Empty
+                );
+                connections.spawn(
+                    handle_connection(
+                        stream,
+                        addr,
+                        acceptor.clone(),
+                        context.clone(),
+                        shutdown.clone(),
+                    )
+                    .instrument(span),
+                );
+            }
+            // Reap finished connections so the set does not grow forever.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            _ = shutdown.changed() => break,
+        }
+    }
+    tracing::info!("websocket server shutting down");
+    while connections.join_next().await.is_some() {}
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    addr: SocketAddr,
+    acceptor: Option<TlsAcceptor>,
+    context: Arc<ServerContext>,
+    shutdown: watch::Receiver<bool>,
+) {
+    match acceptor {
+        None => serve(stream, addr, context, shutdown).await,
+        Some(acceptor) => match acceptor.accept(stream).await {
+            Ok(stream) => serve(stream, addr, context, shutdown).await,
+            Err(err) => tracing::warn!(error = ?err, "TLS handshake failed"),
+        },
+    }
+}
+
+async fn serve<S>(
+    stream: S,
+    addr: SocketAddr,
+    context: Arc<ServerContext>,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let ip = addr.ip();
+    let mut websocket = match accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(err) => {
+            tracing::warn!(error = ?err, "websocket handshake failed");
+            return;
+        }
+    };
+    let hello = match timeout(HELLO_TIMEOUT, read_node_hello(&mut websocket)).await {
+        Ok(Some(hello)) => hello,
+        _ => {
+            tracing::warn!("rejected connection: no hello");
+            let _ = websocket.close(None).await;
+            return;
+        }
+    };
+    if let Err(reason) = context.admission.check_hello(ip, &hello) {
+        let rejected = MasterMessage::Rejected { reason };
+        let _ = websocket
+            .send(Message::binary(borsh::to_vec(&rejected).unwrap()))
+            .await;
+        let _ = websocket.close(None).await;
+        return;
+    }
+
+    let (mut sink, mut stream) = websocket.split();
+    let (outbound_tx, mut outbound_rx) = mpsc::channel::<Message>(OUTBOUND_QUEUE);
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = outbound_rx.recv().await {
+            if sink.send(msg).await.is_err() {
+                break;
+            }
+        }
+        let _ = sink.close().await;
+    });
+
+    let welcome = MasterMessage::Hello {
+        staking_authority: context.staking_authority,
+    };
+    let _ = outbound_tx.try_send(Message::binary(borsh::to_vec(&welcome).unwrap()));
+    let (id, kick) = context.registry.register(addr, hello.miner_authority);
+    let span = Span::current();
+    span.record("worker_id", id);
+    span.record("miner_authority", field::display(hello.miner_authority));
+    tracing::info!("worker connected");
+
+    let mut heartbeat = interval(context.heartbeat.interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            msg = stream.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(err)) => {
+                        tracing::warn!(error = ?err, "connection failed");
+                        break;
+                    }
+                    None => break,
+                };
+                context.registry.touch(id);
+                if msg.is_close() {
+                    break;
+                }
+                // We do not want to send back ping/pong messages.
+                if !(msg.is_binary() || msg.is_text()) {
+                    continue;
+                }
+                if let Ok(NodeMessage::Solution(submitted_solution)) =
+                    borsh::from_slice(&msg.into_data())
+                {
+                    if submitted_solution.miner_authority != hello.miner_authority {
+                        tracing::warn!(
+                            claimed = %submitted_solution.miner_authority,
+                            "dropping solution for another miner authority"
+                        );
+                        context
+                            .metrics
+                            .solutions_rejected
+                            .with_label_values(&["miner_mismatch"])
+                            .inc();
+                        continue;
+                    }
+                    let submitted_solution =
+                        SubmittedSolutionEnum::SubmittedSolution(submitted_solution);
+                    if context.solution_tx.send(submitted_solution).await.is_err() {
+                        tracing::error!("master is gone, closing connection");
+                        break;
+                    }
+                }
+            }
+            _ = heartbeat.tick() => {
+                let silent_for = context
+                    .registry
+                    .get(id)
+                    .and_then(|worker| worker.last_seen.elapsed().ok())
+                    .unwrap_or_default();
+                if silent_for > context.heartbeat.timeout {
+                    tracing::warn!(silent_for = ?silent_for, "worker timed out");
+                    break;
+                }
+                if outbound_tx.try_send(Message::Ping(vec![])).is_err() {
+                    tracing::warn!("worker is not keeping up, disconnecting");
+                    break;
+                }
+            }
+            _ = kick.notified() => {
+                tracing::info!("worker kicked");
+                break;
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    context.registry.remove(id);
+    // Dropping the queue lets the writer flush what is left and close.
+    drop(outbound_tx);
+    let _ = writer.await;
+    tracing::info!("worker disconnected");
+}
+
+/// Waits for the first data message of a connection, which must be the
+/// node's hello.
+async fn read_node_hello<S>(websocket: &mut WebSocketStream<S>) -> Option<NodeHello>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    while let Some(msg) = websocket.next().await {
+        let msg = msg.ok()?;
+        if msg.is_binary() || msg.is_text() {
+            return match borsh::from_slice(&msg.into_data()) {
+                Ok(NodeMessage::Hello(hello)) => Some(hello),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::sleep;
+    use tokio_tungstenite::{connect_async, MaybeTlsStream};
+
+    use super::*;
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    /// Serves nodes on a free port until the returned sender is dropped.
+    async fn start(
+        heartbeat: Heartbeat,
+        registry: WorkerRegistry,
+    ) -> (SocketAddr, watch::Sender<bool>) {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = server.local_addr().unwrap();
+        let (solution_tx, _) = mpsc::channel(1);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let context = ServerContext {
+            staking_authority: Pubkey::new_unique(),
+            heartbeat,
+            admission: Arc::new(Admission::default()),
+            registry,
+            metrics: Arc::new(Metrics::new()),
+            solution_tx,
+        };
+        tokio::spawn(accept_nodes(server, None, context, shutdown_rx));
+        (address, shutdown)
+    }
+
+    /// Connects and goes through the hellos, like a node does.
+    async fn join(address: SocketAddr) -> Client {
+        let (mut client, _) = connect_async(format!("ws://{}", address)).await.unwrap();
+        let hello = NodeMessage::Hello(NodeHello {
+            miner_authority: Pubkey::new_unique(),
+            token: None,
+        });
+        client
+            .send(Message::binary(borsh::to_vec(&hello).unwrap()))
+            .await
+            .unwrap();
+        let welcome = client.next().await.unwrap().unwrap();
+        assert!(matches!(
+            borsh::from_slice(&welcome.into_data()),
+            Ok(MasterMessage::Hello { .. })
+        ));
+        client
+    }
+
+    /// Reads until the master closes the connection.
+    async fn wait_for_close(client: &mut Client) {
+        let closed = async {
+            while let Some(Ok(msg)) = client.next().await {
+                if msg.is_close() {
+                    break;
+                }
+            }
+        };
+        timeout(Duration::from_secs(10), closed)
+            .await
+            .expect("the master did not close the connection");
+    }
+
+    async fn wait_for_workers(registry: &WorkerRegistry, count: usize) {
+        let registered = async {
+            while registry.len() != count {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(10), registered)
+            .await
+            .expect("timed out waiting for the workers");
+    }
+
+    #[tokio::test]
+    async fn silent_workers_are_dropped() {
+        let registry = WorkerRegistry::new();
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(20),
+            timeout: Duration::from_millis(100),
+        };
+        let (address, _shutdown) = start(heartbeat, registry.clone()).await;
+        let mut silent = join(address).await;
+        let mut chatty = join(address).await;
+        wait_for_workers(&registry, 2).await;
+        let chatty_id = registry.workers()[1].id;
+
+        // Anything the node sends keeps it alive, not just solutions.
+        for _ in 0..20 {
+            chatty.send(Message::Ping(vec![])).await.unwrap();
+            sleep(Duration::from_millis(20)).await;
+        }
+        wait_for_close(&mut silent).await;
+        let workers = registry.workers();
+        assert_eq!(workers.len(), 1);
+        assert_eq!(workers[0].id, chatty_id);
+    }
+
+    #[tokio::test]
+    async fn kicked_workers_are_disconnected() {
+        let registry = WorkerRegistry::new();
+        let (address, _shutdown) = start(HEARTBEAT, registry.clone()).await;
+        let mut client = join(address).await;
+        wait_for_workers(&registry, 1).await;
+        let id = registry.workers()[0].id;
+
+        assert!(registry.kick(id));
+        wait_for_close(&mut client).await;
+        assert!(registry.is_empty());
+        assert!(!registry.kick(id));
+    }
+
+    #[tokio::test]
+    async fn a_taken_host_is_an_error() {
+        let (address, _shutdown) = start(HEARTBEAT, WorkerRegistry::new()).await;
+        let (solution_tx, _) = mpsc::channel(1);
+        let (_, shutdown_rx) = watch::channel(false);
+        let served = start_websocket_server(
+            address.to_string(),
+            Pubkey::new_unique(),
+            None,
+            Arc::new(Admission::default()),
+            WorkerRegistry::new(),
+            Arc::new(Metrics::new()),
+            solution_tx,
+            shutdown_rx,
+        )
+        .await;
+        assert_eq!(served.unwrap_err().kind(), io::ErrorKind::AddrInUse);
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::history::{EpochHistory, EpochRecord};
+use crate::ledger::RewardLedger;
+use crate::Challenge;
+
+/// What the master is currently doing for one staking authority.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthorityStatus {
+    pub challenge: Challenge,
+    pub best_difficulty: u32,
+    pub best_submitter: Option<Pubkey>,
+    pub solutions: usize,
+}
+
+/// Last known SOL balance of the account paying transaction fees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeePayerStatus {
+    pub address: Pubkey,
+    pub lamports: u64,
+    /// Below the configured minimum.
+    pub low: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct StatusInner {
+    pub authorities: HashMap<Pubkey, AuthorityStatus>,
+    pub ledger: RewardLedger,
+    pub history: EpochHistory,
+    /// Unknown until the balance has been checked once.
+    pub fee_payer: Option<FeePayerStatus>,
+}
+
+/// Read model of the master, shared with the HTTP API.
+#[derive(Debug, Clone, Default)]
+pub struct PoolStatus {
+    inner: Arc<RwLock<StatusInner>>,
+    paused: Arc<AtomicBool>,
+    out_of_funds: Arc<AtomicBool>,
+}
+
+impl PoolStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_history(history: EpochHistory) -> Self {
+        let status = Self::default();
+        status.write().history = history;
+        status
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, StatusInner> {
+        self.inner.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, StatusInner> {
+        self.inner.write().unwrap()
+    }
+
+    /// While paused, the master keeps collecting solutions but does not
+    /// submit them on chain.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// While out of funds, the master turns solutions away and does not
+    /// submit, until the fee payer is topped up.
+    pub fn is_out_of_funds(&self) -> bool {
+        self.out_of_funds.load(Ordering::Relaxed)
+    }
+
+    pub fn set_out_of_funds(&self, out_of_funds: bool) {
+        self.out_of_funds.store(out_of_funds, Ordering::Relaxed);
+    }
+
+    pub fn record_epoch(&self, record: EpochRecord) {
+        self.write().history.append(record);
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use rustls_pemfile::Item;
+
+/// Builds the master's TLS configuration from a PEM certificate chain and a
+/// PEM private key.
+pub fn server_config(cert_path: &str, key_path: &str) -> io::Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(Arc::new(config))
+}
+
+/// Builds a node TLS configuration that only trusts the certificates in the
+/// given PEM file, pinning the master to a private CA or a self-signed
+/// certificate. Without it, nodes fall back to the webpki roots.
+pub fn client_config(ca_cert: &str) -> io::Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(ca_cert)? {
+        roots
+            .add(&cert)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    }
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+fn load_certs(path: &str) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader)?;
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificates found in {path}"),
+        ));
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &str) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    for item in rustls_pemfile::read_all(&mut reader)? {
+        match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => {}
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("no private key found in {path}"),
+    ))
+}
@@ -0,0 +1,936 @@
+//! Runs a master, its websocket server and a few nodes in-process against a
+//! `FakeChain`, moving the chain's clock by hand from one epoch to the next.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use distributed_drillx::{
+    admission::Admission,
+    chain::Chain,
+    fake_chain::{FakeChain, LAMPORTS_PER_SIGNATURE},
+    funds::BalanceGuard,
+    get_hash,
+    history::{EpochRecord, SubmissionStatus},
+    ledger::OperatorFee,
+    metrics::Metrics,
+    miner::{ensure_proof, find_bus, ore_error, simulate},
+    proof_pubkey,
+    registry::WorkerRegistry,
+    scheduler::{EpochEvent, EpochScheduler},
+    serve_websocket,
+    status::PoolStatus,
+    tls::{client_config, server_config},
+    ChallengeInput, MasterMessage, MasterNode, NodeHashComputer, NodeMessage, SubmittedSolution,
+    SubmittedSolutionEnum,
+};
+use drillx::Solution;
+use ore_api::consts::{ONE_MINUTE, TOLERANCE};
+use ore_api::error::OreError;
+use rustls::ServerConfig;
+use solana_sdk::{
+    instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer,
+    transaction::Transaction,
+};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::{broadcast, mpsc, watch};
+use tungstenite::{client_tls_with_config, Connector};
+
+const EPOCH: u64 = ONE_MINUTE as u64;
+const START: i64 = 1_700_000_000;
+const BASE_REWARD: u64 = 1_000;
+
+struct Pool {
+    chain: Arc<FakeChain>,
+    authority: Pubkey,
+    address: SocketAddr,
+    tx: mpsc::Sender<SubmittedSolutionEnum>,
+    status: PoolStatus,
+    metrics: Arc<Metrics>,
+    registry: WorkerRegistry,
+    shutdown: watch::Sender<bool>,
+    runtime: Runtime,
+    master: JoinHandle<()>,
+}
+
+/// What the master shares with the rest of the pool, for `configure`.
+struct Shared {
+    chain: Arc<FakeChain>,
+    metrics: Arc<Metrics>,
+    status: PoolStatus,
+}
+
+/// How the pool's websocket server accepts nodes.
+#[derive(Default)]
+struct Serving {
+    tls: Option<Arc<ServerConfig>>,
+    admission: Admission,
+}
+
+impl Pool {
+    fn start() -> Self {
+        Self::start_with(|master_node, _| master_node)
+    }
+
+    /// Starts a pool whose master is first adjusted by `configure`.
+    fn start_with(configure: impl FnOnce(MasterNode, &Shared) -> MasterNode) -> Self {
+        Self::start_serving(Serving::default(), configure)
+    }
+
+    fn start_serving(
+        serving: Serving,
+        configure: impl FnOnce(MasterNode, &Shared) -> MasterNode,
+    ) -> Self {
+        let chain = Arc::new(FakeChain::new(START));
+        chain.set_base_reward(BASE_REWARD);
+        let keypair = Keypair::new();
+        let authority = keypair.pubkey();
+        let proof = chain.open_proof(authority, [7; 32]);
+
+        let runtime = Runtime::new().unwrap();
+        let listener = runtime.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel(1_000);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let registry = WorkerRegistry::new();
+        let metrics = Arc::new(Metrics::new());
+        let status = PoolStatus::new();
+        runtime.spawn(serve_websocket(
+            listener,
+            authority,
+            serving.tls,
+            Arc::new(serving.admission),
+            registry.clone(),
+            metrics.clone(),
+            tx.clone(),
+            shutdown_rx,
+        ));
+
+        let master_node = MasterNode::new(
+            chain.clone(),
+            keypair,
+            [(authority, proof)].into_iter().collect(),
+            rx,
+            registry.clone(),
+            metrics.clone(),
+            status.clone(),
+        )
+        .with_clock(chain.clock());
+        let shared = Shared {
+            chain: chain.clone(),
+            metrics: metrics.clone(),
+            status: status.clone(),
+        };
+        let mut master_node = configure(master_node, &shared);
+        let master = thread::spawn(move || master_node.run());
+
+        Self {
+            chain,
+            authority,
+            address,
+            tx,
+            status,
+            metrics,
+            registry,
+            shutdown,
+            runtime,
+            master,
+        }
+    }
+
+    fn connect(&self) -> Node {
+        let miner_authority = Pubkey::new_unique();
+        let mut socket = NodeHashComputer::connect(format!("ws://{}", self.address), None).unwrap();
+        NodeHashComputer::send_hello(&mut socket, miner_authority, None);
+        assert_eq!(NodeHashComputer::receive_hello(&mut socket), self.authority);
+        Node {
+            socket,
+            miner_authority,
+        }
+    }
+
+    /// Polls `condition` until it holds, failing the test after a while.
+    fn wait_for(&self, what: &str, condition: impl Fn(&Pool) -> bool) {
+        let started = Instant::now();
+        while !condition(self) {
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "timed out waiting for {}",
+                what
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Has `node` hash the current challenge and waits for the master to
+    /// process the solution.
+    fn mine(&self, node: &mut Node) -> (Solution, u32) {
+        let challenge =
+            NodeHashComputer::receive_challenge(self.chain.as_ref(), self.authority).challenge;
+        // Hashing once per thread is enough at the fake chain's difficulty.
+        let (hash, nonce) = get_hash(ChallengeInput::new(challenge, 0), self.chain.clock());
+        let solution = Solution::new(hash.d, nonce.to_le_bytes());
+        assert!(solution.is_valid(&challenge));
+        self.send(node, node.miner_authority, to_bytes(&solution));
+        (solution, hash.difficulty())
+    }
+
+    fn send(&self, node: &mut Node, miner_authority: Pubkey, solution: [u8; 24]) {
+        let processed = self.processed();
+        let message = NodeMessage::Solution(SubmittedSolution {
+            staking_authority: self.authority,
+            miner_authority,
+            solution,
+        });
+        NodeHashComputer::send_solution(&mut node.socket, borsh::to_vec(&message).unwrap());
+        self.wait_for("the solution to be processed", |pool| {
+            pool.processed() > processed
+        });
+    }
+
+    /// Solutions that reached the master, or were dropped by the server.
+    fn processed(&self) -> u64 {
+        self.metrics.solutions_received.get()
+            + self
+                .metrics
+                .solutions_rejected
+                .with_label_values(&["miner_mismatch"])
+                .get()
+    }
+
+    /// Moves the chain past the epoch's cutoff and asks the master to submit.
+    /// Returns the recorded epoch, if the master closed it.
+    fn end_epoch(&self, seconds: u64) -> Option<EpochRecord> {
+        let epochs = self.status.read().history.len();
+        let skipped = self.skipped();
+        self.chain.advance(seconds);
+        self.tx
+            .blocking_send(SubmittedSolutionEnum::NewEpoch(self.authority))
+            .unwrap();
+        // Every submission is recorded, once the rewards are settled.
+        self.wait_for("the epoch to be processed", |pool| {
+            pool.status.read().history.len() > epochs || pool.skipped() > skipped
+        });
+        self.status.read().history.records().get(epochs).cloned()
+    }
+
+    fn skipped(&self) -> u64 {
+        self.metrics
+            .submissions
+            .with_label_values(&["skipped"])
+            .get()
+    }
+
+    fn stop(self) {
+        let Pool {
+            tx,
+            shutdown,
+            runtime,
+            master,
+            ..
+        } = self;
+        shutdown.send(true).unwrap();
+        drop(tx);
+        // The master returns once the server dropped its sender.
+        master.join().unwrap();
+        drop(runtime);
+    }
+}
+
+struct Node {
+    socket: tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<std::net::TcpStream>>,
+    miner_authority: Pubkey,
+}
+
+fn to_bytes(solution: &Solution) -> [u8; 24] {
+    [solution.d.as_slice(), solution.n.as_slice()]
+        .concat()
+        .try_into()
+        .unwrap()
+}
+
+/// Waits for the next event of a scheduler.
+fn next_event(events: &mut broadcast::Receiver<EpochEvent>) -> EpochEvent {
+    let started = Instant::now();
+    loop {
+        match events.try_recv() {
+            Ok(event) => return event,
+            Err(broadcast::error::TryRecvError::Empty) => {
+                assert!(
+                    started.elapsed() < Duration::from_secs(30),
+                    "timed out waiting for an epoch event"
+                );
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(err) => panic!("could not receive epoch event: {}", err),
+        }
+    }
+}
+
+/// Mirrors the ledger's split: by shares, dust to the most shares.
+fn expected_split(reward: u64, shares: &HashMap<Pubkey, u64>) -> HashMap<Pubkey, u64> {
+    let total: u64 = shares.values().sum();
+    let mut split: HashMap<Pubkey, u64> = shares
+        .iter()
+        .map(|(miner, n)| (*miner, (reward as u128 * *n as u128 / total as u128) as u64))
+        .collect();
+    let dust = reward - split.values().sum::<u64>();
+    let top = shares
+        .iter()
+        .max_by_key(|(miner, n)| (**n, **miner))
+        .map(|(miner, _)| *miner)
+        .unwrap();
+    *split.get_mut(&top).unwrap() += dust;
+    split
+}
+
+#[test]
+fn epochs_submit_the_best_solution_and_split_rewards() {
+    let pool = Pool::start();
+    let mut nodes: Vec<Node> = (0..3).map(|_| pool.connect()).collect();
+    pool.wait_for("nodes to register", |pool| pool.registry.len() == 3);
+
+    let mut balances: HashMap<Pubkey, u64> = HashMap::new();
+    let mut accepted = 0;
+    for epoch in 0..3 {
+        let proof = pool.chain.proof(pool.authority).unwrap();
+        let mut shares: HashMap<Pubkey, u64> = HashMap::new();
+        let mut best: Option<(Solution, u32, Pubkey)> = None;
+        // The first node mines twice: only its best solution is submitted,
+        // but both count as shares.
+        for index in [0, 1, 2, 0] {
+            let node = &mut nodes[index];
+            let (solution, difficulty) = pool.mine(node);
+            accepted += 1;
+            *shares.entry(node.miner_authority).or_default() += 1;
+            if best.is_none_or(|(_, best, _)| difficulty > best) {
+                best = Some((solution, difficulty, node.miner_authority));
+            }
+        }
+        let (best_solution, best_difficulty, best_miner) = best.unwrap();
+        {
+            let status = pool.status.read();
+            let authority = &status.authorities[&pool.authority];
+            assert_eq!(authority.best_difficulty, best_difficulty);
+            assert_eq!(authority.best_submitter, Some(best_miner));
+            assert_eq!(authority.solutions, 4);
+        }
+
+        let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+        assert_eq!(
+            record.status,
+            SubmissionStatus::Confirmed,
+            "epoch {}",
+            epoch
+        );
+        assert_eq!(record.challenge, proof.challenge);
+        assert_eq!(record.solutions, 4);
+        assert_eq!(record.contributors, 3);
+        assert_eq!(record.best_difficulty, best_difficulty);
+        assert_eq!(record.best_submitter, best_miner);
+        assert_eq!(record.ended_at, pool.chain.unix_timestamp() as u64);
+
+        // The chain received the best solution through a mine instruction.
+        let mine = pool
+            .chain
+            .landed_instructions()
+            .into_iter()
+            .rfind(|ix| ix.program_id == ore_api::ID)
+            .unwrap();
+        let expected =
+            ore_api::instruction::mine(pool.authority, pool.authority, find_bus(), best_solution);
+        assert_eq!(mine, expected);
+
+        let new_proof = pool.chain.proof(pool.authority).unwrap();
+        let reward = new_proof.total_rewards - proof.total_rewards;
+        assert_eq!(reward, BASE_REWARD << best_difficulty);
+        assert_eq!(record.reward, reward);
+        assert_ne!(new_proof.challenge, proof.challenge);
+        assert_eq!(
+            pool.status.read().authorities[&pool.authority].challenge,
+            new_proof.challenge
+        );
+
+        for (miner, amount) in expected_split(reward, &shares) {
+            *balances.entry(miner).or_default() += amount;
+        }
+        let status = pool.status.read();
+        for (miner, balance) in &balances {
+            assert_eq!(status.ledger.account(miner).balance, *balance);
+        }
+    }
+
+    assert_eq!(pool.metrics.solutions_accepted.get(), accepted);
+    assert_eq!(
+        pool.metrics
+            .submissions
+            .with_label_values(&["landed"])
+            .get(),
+        3
+    );
+    assert_eq!(pool.status.read().history.len(), 3);
+    pool.stop();
+}
+
+/// Writes a fresh self-signed certificate for `localhost` and its key.
+/// Returns the paths of both PEM files.
+fn self_signed_certificate() -> (String, String) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = std::env::temp_dir().join(format!("drillx-tls-{}", Pubkey::new_unique()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+    fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+    fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+    (
+        cert_path.to_string_lossy().into_owned(),
+        key_path.to_string_lossy().into_owned(),
+    )
+}
+
+#[test]
+fn nodes_only_trust_the_pinned_certificate() {
+    let (cert, key) = self_signed_certificate();
+    let pool = Pool::start_serving(
+        Serving {
+            tls: Some(server_config(&cert, &key).unwrap()),
+            ..Serving::default()
+        },
+        |master_node, _| master_node,
+    );
+    let url = format!("wss://localhost:{}", pool.address.port());
+
+    // Pinned to the master's certificate, the node gets through.
+    let mut socket =
+        NodeHashComputer::connect(url.clone(), Some(client_config(&cert).unwrap())).unwrap();
+    NodeHashComputer::send_hello(&mut socket, Pubkey::new_unique(), None);
+    assert_eq!(NodeHashComputer::receive_hello(&mut socket), pool.authority);
+    pool.wait_for("the node to register", |pool| pool.registry.len() == 1);
+
+    // The webpki roots do not know a self-signed certificate.
+    assert!(tungstenite::connect(&url).is_err());
+
+    // Neither does a node pinned to another certificate.
+    let (other, _) = self_signed_certificate();
+    let stream = TcpStream::connect(pool.address).unwrap();
+    let connector = Connector::Rustls(client_config(&other).unwrap());
+    assert!(client_tls_with_config(url, stream, None, Some(connector)).is_err());
+    assert_eq!(pool.registry.len(), 1);
+
+    pool.stop();
+}
+
+#[test]
+fn nodes_without_the_token_are_turned_away() {
+    let pool = Pool::start_serving(
+        Serving {
+            admission: Admission::new(vec!["secret".to_string()], vec![], vec![]),
+            ..Serving::default()
+        },
+        |master_node, _| master_node,
+    );
+    let url = format!("ws://{}", pool.address);
+
+    let mut socket = NodeHashComputer::connect(url.clone(), None).unwrap();
+    NodeHashComputer::send_hello(&mut socket, Pubkey::new_unique(), None);
+    let reply = socket.read().unwrap();
+    assert_eq!(
+        borsh::from_slice::<MasterMessage>(&reply.into_data()).unwrap(),
+        MasterMessage::Rejected {
+            reason: "missing token from 127.0.0.1".to_string()
+        }
+    );
+    assert!(pool.registry.is_empty());
+
+    let mut socket = NodeHashComputer::connect(url, None).unwrap();
+    NodeHashComputer::send_hello(
+        &mut socket,
+        Pubkey::new_unique(),
+        Some("secret".to_string()),
+    );
+    assert_eq!(NodeHashComputer::receive_hello(&mut socket), pool.authority);
+    pool.wait_for("the node to register", |pool| pool.registry.len() == 1);
+
+    pool.stop();
+}
+
+#[test]
+fn invalid_solutions_are_not_submitted() {
+    let pool = Pool::start();
+    let mut node = pool.connect();
+
+    let miner_authority = node.miner_authority;
+    pool.send(&mut node, miner_authority, [0; 24]);
+    let other_miner = Pubkey::new_unique();
+    pool.send(&mut node, other_miner, [0; 24]);
+    assert_eq!(
+        pool.metrics
+            .solutions_rejected
+            .with_label_values(&["invalid"])
+            .get(),
+        1
+    );
+    assert_eq!(
+        pool.metrics
+            .solutions_rejected
+            .with_label_values(&["miner_mismatch"])
+            .get(),
+        1
+    );
+
+    // Nothing valid to submit: the epoch stays open.
+    assert!(pool.end_epoch(EPOCH).is_none());
+    assert!(pool.chain.transactions().is_empty());
+    assert_eq!(
+        pool.metrics
+            .submissions
+            .with_label_values(&["skipped"])
+            .get(),
+        1
+    );
+    pool.stop();
+}
+
+#[test]
+fn submitting_inside_the_spam_window_fails() {
+    let pool = Pool::start();
+    let mut node = pool.connect();
+    let proof = pool.chain.proof(pool.authority).unwrap();
+    pool.mine(&mut node);
+
+    let record = pool.end_epoch(10).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Failed);
+    assert!(record.signature.is_none());
+    assert_eq!(record.reward, 0);
+    assert_eq!(pool.chain.proof(pool.authority).unwrap(), proof);
+    assert_eq!(
+        pool.status
+            .read()
+            .ledger
+            .account(&node.miner_authority)
+            .balance,
+        0
+    );
+    pool.stop();
+}
+
+#[test]
+fn scheduler_ends_epochs_on_chain_time() {
+    let pool = Pool::start();
+    let mut node = pool.connect();
+    let proof = pool.chain.proof(pool.authority).unwrap();
+
+    let mut scheduler = EpochScheduler::new(pool.chain.clone(), pool.chain.clock(), &pool.tx);
+    scheduler.add_authority(pool.authority);
+    let mut events = scheduler.subscribe();
+    let scheduler = thread::spawn(move || scheduler.run());
+
+    // 2s of buffer and 4s of assumed latency before the penalty starts.
+    let cutoff = START + ONE_MINUTE + TOLERANCE - 6;
+    assert_eq!(
+        next_event(&mut events),
+        EpochEvent::Started {
+            staking_authority: pool.authority,
+            challenge: proof.challenge,
+            cutoff,
+        }
+    );
+    pool.mine(&mut node);
+
+    // Nothing happens before the cutoff.
+    pool.chain.advance(EPOCH - 2);
+    thread::sleep(Duration::from_millis(100));
+    assert!(events.try_recv().is_err());
+    assert!(pool.chain.transactions().is_empty());
+
+    pool.chain.advance(1);
+    assert_eq!(
+        next_event(&mut events),
+        EpochEvent::CutoffReached {
+            staking_authority: pool.authority,
+            challenge: proof.challenge,
+        }
+    );
+    pool.wait_for("the epoch to be recorded", |pool| {
+        pool.status.read().history.len() == 1
+    });
+    let record = pool.status.read().history.records()[0].clone();
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    assert_eq!(record.ended_at, cutoff as u64);
+
+    // The next epoch starts once the scheduler sees the new challenge. The
+    // submission landed right away, which halves the assumed 4s latency: it
+    // now aims 2s + 2s before the end of the window.
+    pool.chain.advance(2);
+    let new_proof = pool.chain.proof(pool.authority).unwrap();
+    assert_eq!(
+        next_event(&mut events),
+        EpochEvent::Started {
+            staking_authority: pool.authority,
+            challenge: new_proof.challenge,
+            cutoff: cutoff + ONE_MINUTE + TOLERANCE - 4,
+        }
+    );
+
+    let chain = pool.chain.clone();
+    pool.stop();
+    // Wakes the scheduler up at the next cutoff, where it finds the master
+    // gone.
+    chain.advance(EPOCH);
+    scheduler.join().unwrap();
+}
+
+#[test]
+fn solutions_below_the_minimum_difficulty_are_not_submitted() {
+    let pool = Pool::start();
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+
+    // Raised after the solution was accepted: the epoch stays open.
+    pool.chain.set_min_difficulty(64);
+    assert!(pool.end_epoch(EPOCH).is_none());
+    assert!(pool.chain.transactions().is_empty());
+    assert_eq!(
+        pool.metrics
+            .submissions
+            .with_label_values(&["skipped"])
+            .get(),
+        1
+    );
+
+    // New shares are checked against it at intake.
+    pool.mine(&mut node);
+    assert_eq!(
+        pool.metrics
+            .solutions_rejected
+            .with_label_values(&["too_easy"])
+            .get(),
+        1
+    );
+    assert_eq!(pool.metrics.solutions_accepted.get(), 1);
+    pool.stop();
+}
+
+#[test]
+fn a_new_authority_opens_its_proof() {
+    let chain = FakeChain::new(START);
+    let keypair = Keypair::new();
+    let authority = keypair.pubkey();
+    assert!(chain.proof(authority).is_none());
+
+    let proof = ensure_proof(&chain, &keypair, &keypair).unwrap();
+    assert_eq!(chain.proof(authority), Some(proof));
+    assert_eq!(proof.authority, authority);
+    assert_eq!(proof.miner, authority);
+    assert_eq!(proof.last_hash_at, START);
+    let open = chain
+        .landed_instructions()
+        .into_iter()
+        .find(|ix| ix.program_id == ore_api::ID)
+        .unwrap();
+    // The signer is also the miner and payer, so compare keys rather than
+    // the merged account flags.
+    let expected = ore_api::instruction::open(authority, authority, authority);
+    assert_eq!(open.data, expected.data);
+    let keys = |ix: &Instruction| ix.accounts.iter().map(|a| a.pubkey).collect::<Vec<_>>();
+    assert_eq!(keys(&open), keys(&expected));
+    assert_eq!(open.accounts[3].pubkey, proof_pubkey(authority));
+
+    // An existing proof is returned as is.
+    assert_eq!(ensure_proof(&chain, &keypair, &keypair).unwrap(), proof);
+    assert_eq!(chain.transactions().len(), 1);
+}
+
+#[test]
+fn a_share_of_the_reward_is_restaked() {
+    let pool = Pool::start_with(|master_node, _| master_node.with_restake(25));
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    assert_eq!(record.restaked, record.reward / 4);
+    assert!(record.restaked > 0);
+    // The restaked share stays in the proof as stake without any
+    // transaction, the rest is owed to the miner.
+    assert_eq!(pool.chain.transactions().len(), 1);
+    let proof = pool.chain.proof(pool.authority).unwrap();
+    assert_eq!(proof.balance, record.reward);
+    assert_eq!(proof.last_stake_at, START);
+    assert_eq!(
+        pool.status
+            .read()
+            .ledger
+            .account(&node.miner_authority)
+            .balance,
+        record.reward - record.restaked
+    );
+
+    // So the stake multiplier still applies to the next epoch: half the
+    // top balance is worth half the reward again.
+    let mut config = pool.chain.get_config().unwrap();
+    config.top_balance = proof.balance * 2;
+    pool.chain.set_config(config);
+    let (_, difficulty) = pool.mine(&mut node);
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    let base = BASE_REWARD << difficulty;
+    assert_eq!(record.reward, base + base / 2);
+    assert_eq!(pool.chain.transactions().len(), 2);
+    pool.stop();
+}
+
+#[test]
+fn the_operator_fee_is_credited_before_the_miners() {
+    let operator = Pubkey::new_unique();
+    let fee = OperatorFee {
+        account: operator,
+        basis_points: 1_000,
+        fixed: 100,
+    };
+    let pool = Pool::start_with(|master_node, _| master_node.with_fee(fee));
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.fee, record.reward / 10 + 100);
+    let status = pool.status.read();
+    assert_eq!(status.ledger.account(&operator).balance, record.fee);
+    assert_eq!(
+        status.ledger.account(&node.miner_authority).balance,
+        record.reward - record.fee
+    );
+    drop(status);
+
+    // Never more than the reward.
+    assert_eq!(fee.of(50), 50);
+    pool.stop();
+}
+
+#[test]
+fn a_separate_fee_payer_pays_for_transactions() {
+    let fee_payer = Keypair::new();
+    let payer = fee_payer.pubkey();
+    let pool = Pool::start_with(|master_node, _| master_node.with_fee_payer(fee_payer));
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    let tx = &pool.chain.transactions()[0];
+    assert_eq!(tx.message.account_keys[0], payer);
+    assert_eq!(tx.signatures.len(), 2);
+    assert!(tx.message.account_keys.contains(&pool.authority));
+    pool.stop();
+
+    // Opening a proof: the payer covers the rent, the authority owns it.
+    let chain = FakeChain::new(START);
+    let authority = Keypair::new();
+    let fee_payer = Keypair::new();
+    let proof = ensure_proof(&chain, &authority, &fee_payer).unwrap();
+    assert_eq!(proof.authority, authority.pubkey());
+    let tx = &chain.transactions()[0];
+    assert_eq!(tx.message.account_keys[0], fee_payer.pubkey());
+    let open = chain
+        .landed_instructions()
+        .into_iter()
+        .find(|ix| ix.program_id == ore_api::ID)
+        .unwrap();
+    assert_eq!(open.accounts[2].pubkey, fee_payer.pubkey());
+}
+
+#[test]
+fn a_fee_payer_out_of_sol_pauses_the_pool() {
+    let fee_payer = Keypair::new();
+    let payer = fee_payer.pubkey();
+    // Enough for a single two-signature transaction.
+    let min_lamports = 2 * LAMPORTS_PER_SIGNATURE;
+    let pool = Pool::start_with(|master_node, shared| {
+        shared.chain.set_lamports(payer, 3 * LAMPORTS_PER_SIGNATURE);
+        let guard = BalanceGuard::new(
+            shared.chain.clone(),
+            payer,
+            min_lamports,
+            shared.metrics.clone(),
+            shared.status.clone(),
+        )
+        .with_pause(true);
+        master_node
+            .with_fee_payer(fee_payer)
+            .with_balance_guard(guard)
+    });
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    assert_eq!(pool.chain.lamports(payer), LAMPORTS_PER_SIGNATURE);
+
+    // Below the minimum: the next epoch is not submitted and new solutions
+    // are turned away.
+    pool.mine(&mut node);
+    assert!(pool.end_epoch(EPOCH).is_none());
+    assert_eq!(pool.chain.transactions().len(), 1);
+    assert!(pool.status.is_out_of_funds());
+    let fee_payer_status = pool.status.read().fee_payer.unwrap();
+    assert_eq!(fee_payer_status.lamports, LAMPORTS_PER_SIGNATURE);
+    assert!(fee_payer_status.low);
+    assert_eq!(
+        pool.metrics.fee_payer_lamports.get(),
+        LAMPORTS_PER_SIGNATURE as i64
+    );
+    pool.mine(&mut node);
+    assert_eq!(
+        pool.metrics
+            .solutions_rejected
+            .with_label_values(&["out_of_funds"])
+            .get(),
+        1
+    );
+
+    // Topped up, the epoch that was kept open is submitted.
+    pool.chain
+        .set_lamports(payer, 1_000 * LAMPORTS_PER_SIGNATURE);
+    let record = pool.end_epoch(0).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    assert!(!pool.status.is_out_of_funds());
+    pool.stop();
+}
+
+#[test]
+fn simulated_failures_are_not_sent() {
+    let pool = Pool::start_with(|master_node, _| master_node.with_simulation(true));
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+
+    let record = pool.end_epoch(10).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Failed);
+    assert!(pool.chain.transactions().is_empty());
+
+    pool.mine(&mut node);
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    assert_eq!(pool.chain.transactions().len(), 1);
+    pool.stop();
+
+    // The failure is decoded into the program's error.
+    let chain = FakeChain::new(START);
+    let keypair = Keypair::new();
+    let proof = chain.open_proof(keypair.pubkey(), [7; 32]);
+    let (hash, nonce) = get_hash(ChallengeInput::new(proof.challenge, 0), chain.clock());
+    let ix = ore_api::instruction::mine(
+        keypair.pubkey(),
+        keypair.pubkey(),
+        find_bus(),
+        Solution::new(hash.d, nonce.to_le_bytes()),
+    );
+    let tx = Transaction::new_signed_with_payer(
+        std::slice::from_ref(&ix),
+        Some(&keypair.pubkey()),
+        &[&keypair],
+        chain.get_latest_blockhash().unwrap(),
+    );
+    let err = simulate(&chain, &tx, std::slice::from_ref(&ix)).unwrap_err();
+    let err = err.get_transaction_error().unwrap();
+    assert_eq!(ore_error(&err, &[ix]), Some(OreError::Spam));
+    assert!(chain.transactions().is_empty());
+}
+
+#[test]
+fn failed_submissions_are_classified_and_recovered_from() {
+    let pool = Pool::start();
+    let mut node = pool.connect();
+    pool.mine(&mut node);
+    let failures = |reason: &str| {
+        pool.metrics
+            .submission_failures
+            .with_label_values(&[reason])
+            .get()
+    };
+
+    // Too early: the solution is kept and submitted at the next attempt.
+    let record = pool.end_epoch(10).expect("attempt was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Failed);
+    assert_eq!(record.error.unwrap(), OreError::Spam.to_string());
+    assert_eq!(failures("spam"), 1);
+    let record = pool.end_epoch(EPOCH - 10).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    assert_eq!(
+        pool.status
+            .read()
+            .ledger
+            .account(&node.miner_authority)
+            .balance,
+        record.reward
+    );
+
+    // Someone else moved the proof on: the master follows its challenge.
+    pool.mine(&mut node);
+    let mut proof = pool.chain.proof(pool.authority).unwrap();
+    proof.challenge = [9; 32];
+    pool.chain.set_proof(proof);
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Failed);
+    assert_eq!(failures("hash_invalid"), 1);
+    assert_eq!(
+        pool.status.read().authorities[&pool.authority].challenge,
+        [9; 32]
+    );
+
+    // The program epoch is over: the master resets it, then submits.
+    let mut config = pool.chain.get_config().unwrap();
+    config.last_reset_at = pool.chain.unix_timestamp();
+    pool.chain.set_config(config);
+    pool.mine(&mut node);
+    let record = pool.end_epoch(EPOCH).expect("attempt was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Failed);
+    assert_eq!(failures("needs_reset"), 1);
+    assert_eq!(
+        pool.chain.get_config().unwrap().last_reset_at,
+        pool.chain.unix_timestamp()
+    );
+    let record = pool.end_epoch(0).expect("epoch was not recorded");
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    pool.stop();
+}
+
+#[test]
+fn the_master_follows_a_challenge_that_moved_without_it() {
+    let pool = Pool::start();
+    let mut node = pool.connect();
+
+    // The proof moves on without the master submitting anything, like when a
+    // submission it gave up on lands late.
+    let mut proof = pool.chain.proof(pool.authority).unwrap();
+    proof.challenge = [9; 32];
+    pool.chain.set_proof(proof);
+    // Solutions for the new challenge are turned away until it follows.
+    pool.mine(&mut node);
+    assert_eq!(
+        pool.metrics
+            .solutions_rejected
+            .with_label_values(&["invalid"])
+            .get(),
+        1
+    );
+
+    // With nothing valid to submit, the master re-reads the proof.
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.challenge, [7; 32]);
+    assert_eq!(record.status, SubmissionStatus::Failed);
+    assert!(record.signature.is_none());
+    assert!(pool.chain.transactions().is_empty());
+    assert_eq!(
+        pool.status.read().authorities[&pool.authority].challenge,
+        [9; 32]
+    );
+
+    // Solutions for the new challenge are accepted and submitted.
+    pool.mine(&mut node);
+    let record = pool.end_epoch(EPOCH).expect("epoch was not recorded");
+    assert_eq!(record.challenge, [9; 32]);
+    assert_eq!(record.status, SubmissionStatus::Confirmed);
+    pool.stop();
+}